@@ -29,6 +29,7 @@ extern crate failure;
 extern crate serde_derive;
 extern crate rlua;
 extern crate hex;
+extern crate serde_json;
 
 pub mod proto;
 pub mod currency;
@@ -0,0 +1,211 @@
+use exonum::proto::ProtobufConvert;
+
+use std::{error::Error as StdError, fmt};
+
+use super::{proto, value::Value};
+
+/// Type of a function argument or return value declared in the ABI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ValueType {
+    Any,
+    Boolean,
+    Integer,
+    /// Any Lua number, integers included.
+    Number,
+    String,
+    Table,
+}
+
+impl ValueType {
+    pub fn matches(self, value: &Value) -> bool {
+        match (self, value) {
+            (ValueType::Any, _) => true,
+            (ValueType::Boolean, Value::Bool(_)) => true,
+            (ValueType::Integer, Value::Integer(_)) => true,
+            (ValueType::Number, Value::Integer(_)) | (ValueType::Number, Value::Number(_)) => true,
+            (ValueType::String, Value::String(_)) => true,
+            (ValueType::Table, Value::Table(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Parses a transaction argument. Tables are passed as JSON, `any` keeps the string as is.
+    pub fn parse(self, arg: &str) -> Option<Value> {
+        match self {
+            ValueType::Any | ValueType::String => Some(Value::from(arg)),
+            ValueType::Boolean => arg.parse().ok().map(Value::Bool),
+            ValueType::Integer => arg.parse().ok().map(Value::Integer),
+            ValueType::Number => arg.parse().ok().map(Value::Number),
+            ValueType::Table => serde_json::from_str(arg)
+                .ok()
+                .map(|json| Value::from_json(&json))
+                .filter(|value| self.matches(value)),
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ValueType::Any => "any",
+            ValueType::Boolean => "boolean",
+            ValueType::Integer => "integer",
+            ValueType::Number => "number",
+            ValueType::String => "string",
+            ValueType::Table => "table",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ProtobufConvert for ValueType {
+    type ProtoStruct = proto::ValueType;
+
+    fn to_pb(&self) -> proto::ValueType {
+        match self {
+            ValueType::Any => proto::ValueType::ANY,
+            ValueType::Boolean => proto::ValueType::BOOLEAN,
+            ValueType::Integer => proto::ValueType::INTEGER,
+            ValueType::Number => proto::ValueType::NUMBER,
+            ValueType::String => proto::ValueType::STRING,
+            ValueType::Table => proto::ValueType::TABLE,
+        }
+    }
+
+    fn from_pb(pb: proto::ValueType) -> Result<Self, failure::Error> {
+        Ok(match pb {
+            proto::ValueType::ANY => ValueType::Any,
+            proto::ValueType::BOOLEAN => ValueType::Boolean,
+            proto::ValueType::INTEGER => ValueType::Integer,
+            proto::ValueType::NUMBER => ValueType::Number,
+            proto::ValueType::STRING => ValueType::String,
+            proto::ValueType::TABLE => ValueType::Table,
+        })
+    }
+}
+
+/// Declaration of a function exported by a contract.
+#[derive(Clone, Debug, PartialEq, ProtobufConvert)]
+#[exonum(pb = "proto::FunctionAbi", serde_pb_convert)]
+pub struct FunctionAbi {
+    pub name: String,
+    pub args: Vec<ValueType>,
+    pub returns: Vec<ValueType>,
+    /// View functions can be called through the API, others only by transactions.
+    pub view: bool,
+}
+
+impl FunctionAbi {
+    pub fn new(name: &str, args: Vec<ValueType>, returns: Vec<ValueType>, view: bool) -> Self {
+        Self {
+            name: name.to_string(),
+            args,
+            returns,
+            view,
+        }
+    }
+
+    pub fn parse_args(&self, args: &[String]) -> Result<Vec<Value>, AbiError> {
+        self.check_len("arguments", self.args.len(), args.len())?;
+        self.args
+            .iter()
+            .zip(args)
+            .enumerate()
+            .map(|(i, (ty, arg))| {
+                ty.parse(arg).ok_or_else(|| {
+                    AbiError(format!("argument {} of `{}` must be {}", i + 1, self.name, ty))
+                })
+            })
+            .collect()
+    }
+
+    pub fn check_args(&self, args: &[Value]) -> Result<(), AbiError> {
+        self.check_len("arguments", self.args.len(), args.len())?;
+        self.check_types("argument", &self.args, args)
+    }
+
+    pub fn check_returns(&self, returns: &[Value]) -> Result<(), AbiError> {
+        self.check_len("return values", self.returns.len(), returns.len())?;
+        self.check_types("return value", &self.returns, returns)
+    }
+
+    fn check_len(&self, what: &str, expected: usize, actual: usize) -> Result<(), AbiError> {
+        if expected == actual {
+            Ok(())
+        } else {
+            Err(AbiError(format!(
+                "`{}` expects {} {}, got {}",
+                self.name, expected, what, actual
+            )))
+        }
+    }
+
+    fn check_types(&self, what: &str, types: &[ValueType], values: &[Value]) -> Result<(), AbiError> {
+        for (i, (ty, value)) in types.iter().zip(values).enumerate() {
+            if !ty.matches(value) {
+                return Err(AbiError(format!(
+                    "{} {} of `{}` must be {}",
+                    what,
+                    i + 1,
+                    self.name,
+                    ty
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Interface of a contract declared at deployment.
+///
+/// Contracts with an empty ABI accept any calls with string arguments.
+#[derive(Clone, Debug, Default, PartialEq, ProtobufConvert)]
+#[exonum(pb = "proto::Abi", serde_pb_convert)]
+pub struct Abi {
+    pub functions: Vec<FunctionAbi>,
+}
+
+impl Abi {
+    pub fn new(functions: Vec<FunctionAbi>) -> Self {
+        Self { functions }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functions.is_empty()
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionAbi> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Converts transaction arguments into values of the declared types.
+    ///
+    /// Arguments of undeclared functions are passed as strings.
+    pub fn parse_args(&self, fn_name: &str, args: &[String]) -> Result<Vec<Value>, AbiError> {
+        match self.function(fn_name) {
+            Some(function) => function.parse_args(args),
+            None => Ok(args.iter().map(|arg| Value::from(arg.as_str())).collect()),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), AbiError> {
+        for (i, function) in self.functions.iter().enumerate() {
+            if self.functions[..i].iter().any(|f| f.name == function.name) {
+                return Err(AbiError(format!("function `{}` is declared twice", function.name)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Mismatch between a call and the contract ABI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AbiError(pub String);
+
+impl fmt::Display for AbiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl StdError for AbiError {}
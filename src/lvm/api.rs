@@ -1,16 +1,36 @@
 use exonum::{
     api::{self, ServiceApiBuilder, ServiceApiState},
-    blockchain::{self, BlockProof},
-    crypto::PublicKey,
+    blockchain::{self, BlockProof, TransactionMessage},
+    crypto::{Hash, PublicKey, PUBLIC_KEY_LENGTH},
+    explorer::BlockchainExplorer,
     helpers::Height,
-    storage::MapProof,
+    storage::{ListProof, MapProof, ProofListIndex, Snapshot},
 };
 
+use serde_json::Value as JsonValue;
+
+use crate::currency::schema::Schema as CurrencySchema;
+
 use super::{
-    contract::Contract,
-    schema::Schema,
+    abi::Abi,
+    config::LvmConfig,
+    contract::{Contract, StateEntry},
+    event::Event,
+    receipt::Receipt,
+    runner::Runner,
+    schema::{self as lvm_schema, Schema},
+    service::LVM_SERVICE_ID,
+    token::Token,
+    value::Value,
 };
 
+/// Position of the contracts table in the service state hash.
+const CONTRACTS_TABLE: usize = 0;
+/// Position of the receipts table in the service state hash.
+const RECEIPTS_TABLE: usize = 1;
+/// Position of the tokens table in the service state hash.
+const TOKENS_TABLE: usize = 4;
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub struct ContractQuery {
     pub pub_key: PublicKey,
@@ -22,6 +42,178 @@ pub struct ContractInfo {
     pub contract_proof: MapProof<PublicKey, Contract>,
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct CodeQuery {
+    pub code_hash: Hash,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CodeInfo {
+    pub block_proof: BlockProof,
+    pub code_proof: MapProof<Hash, String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AddressQuery {
+    pub author: PublicKey,
+}
+
+/// Address of the next contract created by the author.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct AddressInfo {
+    pub nonce: u64,
+    pub address: PublicKey,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct StateQuery {
+    pub pub_key: PublicKey,
+    pub key: String,
+}
+
+/// Proof of a single contract state entry.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StateProof {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the contracts table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the contract in this table.
+    pub to_contract: MapProof<PublicKey, Contract>,
+    /// Proof of the entry in the contract state, its root is the contract `state_hash`.
+    pub to_entry: MapProof<Hash, StateEntry>,
+}
+
+/// Events emitted by a contract.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContractEvents {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the contracts table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the contract in this table.
+    pub to_contract: MapProof<PublicKey, Contract>,
+    /// Proof of the events list, its root is the contract `events_hash`.
+    /// `None` if the contract has emitted no events.
+    pub proof: Option<ListProof<Event>>,
+    /// List of above events.
+    pub events: Vec<Event>,
+}
+
+/// Events emitted by a transaction.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TxEvents {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the receipts table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the transaction receipt in this table.
+    pub to_receipt: MapProof<Hash, Receipt>,
+    /// Proof of the events list, its root is the receipt `events_hash`.
+    /// `None` if the transaction has emitted no events.
+    pub proof: Option<ListProof<Event>>,
+    /// List of above events.
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct ReceiptQuery {
+    pub tx_hash: Hash,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceiptInfo {
+    pub block_proof: BlockProof,
+    pub receipt_proof: MapProof<Hash, Receipt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ViewQuery {
+    pub pub_key: PublicKey,
+    pub fn_name: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    pub caller: Option<PublicKey>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ViewResult {
+    pub returns: Vec<JsonValue>,
+    pub gas_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TokenQuery {
+    pub pub_key: PublicKey,
+}
+
+/// Token history.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenHistory {
+    /// Proof of the list of transaction hashes, its root is the token `history_hash`.
+    pub proof: ListProof<Hash>,
+    /// List of above transactions.
+    pub transactions: Vec<TransactionMessage>,
+}
+
+/// Token information.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenInfo {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the tokens table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the token in this table.
+    pub to_token: MapProof<PublicKey, Token>,
+    /// History of the token, if it exists.
+    pub token_history: Option<TokenHistory>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TokenBalanceQuery {
+    pub token: PublicKey,
+    pub holder: PublicKey,
+}
+
+/// Proof of a holder balance. Missing holders have no tokens.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenBalanceProof {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the tokens table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the token in this table.
+    pub to_token: MapProof<PublicKey, Token>,
+    /// Proof of the balance, its root is the token `balances_hash`.
+    pub to_balance: MapProof<PublicKey, u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TokenAllowanceQuery {
+    pub token: PublicKey,
+    pub owner: PublicKey,
+    pub spender: PublicKey,
+}
+
+/// Proof of an allowance, stored under `schema::allowance_key`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenAllowanceProof {
+    /// Proof of the last block.
+    pub block_proof: BlockProof,
+    /// Proof of the tokens table in the service state.
+    pub to_table: MapProof<Hash, Hash>,
+    /// Proof of the token in this table.
+    pub to_token: MapProof<PublicKey, Token>,
+    /// Proof of the allowance, its root is the token `allowances_hash`.
+    pub to_allowance: MapProof<Hash, u64>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct TokenHolder {
+    pub holder: PublicKey,
+    pub balance: u64,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct PublicApi;
 
@@ -44,9 +236,351 @@ impl PublicApi {
         })
     }
 
+    pub fn code_info(state: &ServiceApiState, query: CodeQuery) -> api::Result<CodeInfo> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let code_proof: MapProof<Hash, String> = lvm_schema.code_store().get_proof(query.code_hash);
+
+        Ok(CodeInfo {
+            block_proof,
+            code_proof,
+        })
+    }
+
+    pub fn next_address(state: &ServiceApiState, query: AddressQuery) -> api::Result<AddressInfo> {
+        let snapshot = state.snapshot();
+        let lvm_schema = Schema::new(&snapshot);
+
+        let nonce = lvm_schema.nonce(&query.author);
+        Ok(AddressInfo {
+            nonce,
+            address: lvm_schema::contract_address(&query.author, nonce),
+        })
+    }
+
+    pub fn state_entry(state: &ServiceApiState, query: StateQuery) -> api::Result<StateProof> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, CONTRACTS_TABLE);
+
+        let to_contract: MapProof<PublicKey, Contract> =
+            lvm_schema.contracts().get_proof(query.pub_key);
+
+        let to_entry: MapProof<Hash, StateEntry> = lvm_schema
+            .contract_state(&query.pub_key)
+            .get_proof(lvm_schema::state_key(&query.key));
+
+        Ok(StateProof {
+            block_proof,
+            to_table,
+            to_contract,
+            to_entry,
+        })
+    }
+
+    pub fn contract_events(
+        state: &ServiceApiState,
+        query: ContractQuery,
+    ) -> api::Result<ContractEvents> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, CONTRACTS_TABLE);
+
+        let to_contract: MapProof<PublicKey, Contract> =
+            lvm_schema.contracts().get_proof(query.pub_key);
+
+        let history = lvm_schema.contract_events(&query.pub_key);
+        let proof = events_proof(&history);
+        let events = history.iter().collect::<Vec<_>>();
+
+        Ok(ContractEvents {
+            block_proof,
+            to_table,
+            to_contract,
+            proof,
+            events,
+        })
+    }
+
+    /// Returns events emitted by the given transaction.
+    pub fn tx_events(state: &ServiceApiState, query: ReceiptQuery) -> api::Result<TxEvents> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, RECEIPTS_TABLE);
+
+        let to_receipt: MapProof<Hash, Receipt> = lvm_schema.receipts().get_proof(query.tx_hash);
+
+        let list = lvm_schema.tx_events(&query.tx_hash);
+        let proof = events_proof(&list);
+        let events = list.iter().collect::<Vec<_>>();
+
+        Ok(TxEvents {
+            block_proof,
+            to_table,
+            to_receipt,
+            proof,
+            events,
+        })
+    }
+
+    pub fn receipt_info(state: &ServiceApiState, query: ReceiptQuery) -> api::Result<ReceiptInfo> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let receipt_proof: MapProof<Hash, Receipt> = lvm_schema.receipts().get_proof(query.tx_hash);
+
+        Ok(ReceiptInfo {
+            block_proof,
+            receipt_proof,
+        })
+    }
+
+    pub fn contract_abi(state: &ServiceApiState, query: ContractQuery) -> api::Result<Abi> {
+        let snapshot = state.snapshot();
+        Schema::new(&snapshot)
+            .contract(&query.pub_key)
+            .map(|contract| contract.abi)
+            .ok_or_else(|| api::Error::NotFound("Contract not found".to_owned()))
+    }
+
+    pub fn call_view(state: &ServiceApiState, query: ViewQuery) -> api::Result<ViewResult> {
+        let mut fork = state.blockchain().fork();
+
+        let contract = Schema::new(&fork)
+            .contract(&query.pub_key)
+            .ok_or_else(|| api::Error::NotFound("Contract not found".to_owned()))?;
+        let contract_wallet = CurrencySchema::new(&fork)
+            .wallet(&query.pub_key)
+            .ok_or_else(|| api::Error::NotFound("Contract wallet not found".to_owned()))?;
+        let config = LvmConfig::actual(&fork);
+
+        if let Some(function) = contract.abi.function(&query.fn_name) {
+            if !function.view {
+                return Err(api::Error::BadRequest(format!(
+                    "`{}` is not a view function",
+                    query.fn_name
+                )));
+            }
+        }
+        let args = contract
+            .abi
+            .parse_args(&query.fn_name, &query.args)
+            .map_err(|e| api::Error::BadRequest(e.to_string()))?;
+
+        let runner = Runner {
+            contract,
+            contract_wallet,
+            fork: &mut fork,
+            caller: query
+                .caller
+                .unwrap_or_else(|| PublicKey::new([0; PUBLIC_KEY_LENGTH])),
+            tx_hash: None,
+            value: 0,
+            // Views are limited as a transaction with the maximum gas would be.
+            gas_limit: config.max_gas,
+            memory_limit: config.memory_limit as usize,
+            call_stack: Vec::new(),
+        };
+
+        // The fork is dropped afterwards, so nothing the call does is committed.
+        let execution = runner
+            .exec(&query.fn_name, args)
+            .map_err(|e| api::Error::BadRequest(e.to_string()))?;
+
+        Ok(ViewResult {
+            returns: execution.returns.iter().map(Value::to_json).collect(),
+            gas_used: execution.gas_used,
+        })
+    }
+
+    /// Returns all issued tokens.
+    pub fn tokens(state: &ServiceApiState, _query: ()) -> api::Result<Vec<Token>> {
+        let snapshot = state.snapshot();
+        let lvm_schema = Schema::new(&snapshot);
+        Ok(lvm_schema.tokens().values().collect())
+    }
+
+    pub fn token_info(state: &ServiceApiState, query: TokenQuery) -> api::Result<TokenInfo> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, TOKENS_TABLE);
+
+        let to_token: MapProof<PublicKey, Token> = lvm_schema.tokens().get_proof(query.pub_key);
+
+        let explorer = BlockchainExplorer::new(state.blockchain());
+
+        let token_history = lvm_schema.token(&query.pub_key).map(|_| {
+            let history = lvm_schema.token_history(&query.pub_key);
+            let proof = history.get_range_proof(0, history.len());
+
+            let transactions = history
+                .iter()
+                .map(|record| explorer.transaction_without_proof(&record).unwrap())
+                .collect::<Vec<_>>();
+
+            TokenHistory {
+                proof,
+                transactions,
+            }
+        });
+
+        Ok(TokenInfo {
+            block_proof,
+            to_table,
+            to_token,
+            token_history,
+        })
+    }
+
+    pub fn token_balance(
+        state: &ServiceApiState,
+        query: TokenBalanceQuery,
+    ) -> api::Result<TokenBalanceProof> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, TOKENS_TABLE);
+
+        let to_token: MapProof<PublicKey, Token> = lvm_schema.tokens().get_proof(query.token);
+
+        let to_balance: MapProof<PublicKey, u64> = lvm_schema
+            .token_balances(&query.token)
+            .get_proof(query.holder);
+
+        Ok(TokenBalanceProof {
+            block_proof,
+            to_table,
+            to_token,
+            to_balance,
+        })
+    }
+
+    pub fn token_allowance(
+        state: &ServiceApiState,
+        query: TokenAllowanceQuery,
+    ) -> api::Result<TokenAllowanceProof> {
+        let snapshot = state.snapshot();
+        let general_schema = blockchain::Schema::new(&snapshot);
+        let lvm_schema = Schema::new(&snapshot);
+
+        let max_height = general_schema.block_hashes_by_height().len() - 1;
+        let block_proof = general_schema
+            .block_and_precommits(Height(max_height))
+            .unwrap();
+
+        let to_table: MapProof<Hash, Hash> =
+            general_schema.get_proof_to_service_table(LVM_SERVICE_ID, TOKENS_TABLE);
+
+        let to_token: MapProof<PublicKey, Token> = lvm_schema.tokens().get_proof(query.token);
+
+        let to_allowance: MapProof<Hash, u64> = lvm_schema
+            .token_allowances(&query.token)
+            .get_proof(lvm_schema::allowance_key(&query.owner, &query.spender));
+
+        Ok(TokenAllowanceProof {
+            block_proof,
+            to_table,
+            to_token,
+            to_allowance,
+        })
+    }
+
+    /// Returns holders of the token with their balances.
+    pub fn token_holders(
+        state: &ServiceApiState,
+        query: TokenQuery,
+    ) -> api::Result<Vec<TokenHolder>> {
+        let snapshot = state.snapshot();
+        let lvm_schema = Schema::new(&snapshot);
+        if lvm_schema.token(&query.pub_key).is_none() {
+            return Err(api::Error::NotFound("Token not found".to_owned()));
+        }
+        Ok(lvm_schema
+            .token_balances(&query.pub_key)
+            .iter()
+            .map(|(holder, balance)| TokenHolder { holder, balance })
+            .collect())
+    }
+
     pub fn wire(builder: &mut ServiceApiBuilder) {
         builder
             .public_scope()
-            .endpoint("v1/contracts/info", Self::contract_info);
+            .endpoint("v1/contracts/info", Self::contract_info)
+            .endpoint("v1/contracts/code", Self::code_info)
+            .endpoint("v1/contracts/abi", Self::contract_abi)
+            .endpoint("v1/contracts/address", Self::next_address)
+            .endpoint("v1/contracts/state", Self::state_entry)
+            .endpoint("v1/contracts/events", Self::contract_events)
+            .endpoint("v1/contracts/tx_events", Self::tx_events)
+            .endpoint("v1/contracts/receipt", Self::receipt_info)
+            .endpoint("v1/tokens", Self::tokens)
+            .endpoint("v1/tokens/info", Self::token_info)
+            .endpoint("v1/tokens/balance", Self::token_balance)
+            .endpoint("v1/tokens/allowance", Self::token_allowance)
+            .endpoint("v1/tokens/holders", Self::token_holders)
+            .endpoint_mut("v1/contracts/call", Self::call_view);
+    }
+}
+
+/// Proves the whole events list, an empty list has nothing to prove.
+fn events_proof<T: AsRef<dyn Snapshot>>(
+    list: &ProofListIndex<T, Event>,
+) -> Option<ListProof<Event>> {
+    if list.is_empty() {
+        None
+    } else {
+        Some(list.get_range_proof(0, list.len()))
     }
 }
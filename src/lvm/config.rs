@@ -0,0 +1,46 @@
+use exonum::{blockchain::Schema as CoreSchema, storage::Snapshot};
+
+use super::service::SERVICE_NAME;
+
+/// Memory available to a single contract call unless configured otherwise, in bytes.
+pub const DEFAULT_MEMORY_LIMIT: u64 = 16 * 1024 * 1024;
+/// Highest gas limit a transaction may request unless configured otherwise.
+pub const DEFAULT_MAX_GAS: u64 = 10_000_000;
+
+/// LVM service configuration shared by all validators.
+///
+/// Stored in the blockchain configuration under the service name, so it can only be
+/// changed through the configuration service.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct LvmConfig {
+    /// Memory ceiling of the Lua VM for each contract call, in bytes.
+    pub memory_limit: u64,
+    /// Highest gas limit a transaction may request, so that no call can stall validators.
+    #[serde(default = "default_max_gas")]
+    pub max_gas: u64,
+}
+
+fn default_max_gas() -> u64 {
+    DEFAULT_MAX_GAS
+}
+
+impl Default for LvmConfig {
+    fn default() -> Self {
+        Self {
+            memory_limit: DEFAULT_MEMORY_LIMIT,
+            max_gas: DEFAULT_MAX_GAS,
+        }
+    }
+}
+
+impl LvmConfig {
+    /// Returns the configuration of the LVM service which is currently in effect.
+    pub fn actual<T: AsRef<dyn Snapshot>>(view: T) -> Self {
+        CoreSchema::new(view)
+            .actual_configuration()
+            .services
+            .get(SERVICE_NAME)
+            .and_then(|value| serde_json::from_value(value.clone()).ok())
+            .unwrap_or_default()
+    }
+}
@@ -1,25 +1,96 @@
 use exonum::{
-    crypto::PublicKey,
+    crypto::{Hash, PublicKey},
 };
 
-use std::collections::HashMap;
-
-use super::proto;
+use super::{abi::Abi, proto, value::Value};
 
 #[derive(Clone, Debug, ProtobufConvert)]
 #[exonum(pb = "proto::Contract", serde_pb_convert)]
 pub struct Contract {
     pub pub_key: PublicKey,
-    pub code: String,
-    pub state: HashMap<String, String>,
+    /// `PublicKey` of the account allowed to upgrade the contract.
+    pub owner: PublicKey,
+    /// `Hash` of the contract code in the code store.
+    pub code_hash: Hash,
+    /// `Hash` of the contract state.
+    pub state_hash: Hash,
+    /// Number of events emitted by the contract.
+    pub events_len: u64,
+    /// `Hash` of the contract events.
+    pub events_hash: Hash,
+    /// Functions exported by the contract.
+    pub abi: Abi,
 }
 
 impl Contract {
-    pub fn new(pub_key: &PublicKey, code: &str) -> Self {
+    pub fn new(
+        pub_key: &PublicKey,
+        owner: &PublicKey,
+        code_hash: &Hash,
+        state_hash: &Hash,
+        events_len: u64,
+        events_hash: &Hash,
+        abi: Abi,
+    ) -> Self {
         Self {
             pub_key: *pub_key,
-            code: code.to_string(),
-            state: HashMap::new(),
+            owner: *owner,
+            code_hash: *code_hash,
+            state_hash: *state_hash,
+            events_len,
+            events_hash: *events_hash,
+            abi,
+        }
+    }
+
+    /// Returns a copy of this contract with updated state hash.
+    pub fn set_state_hash(self, state_hash: &Hash) -> Self {
+        Self {
+            state_hash: *state_hash,
+            ..self
+        }
+    }
+
+    /// Returns a copy of this contract with updated events.
+    pub fn set_events(self, events_len: u64, events_hash: &Hash) -> Self {
+        Self {
+            events_len,
+            events_hash: *events_hash,
+            ..self
+        }
+    }
+
+    /// Returns a copy of this contract with replaced code and its ABI.
+    pub fn set_code(self, code_hash: &Hash, abi: Abi) -> Self {
+        Self {
+            code_hash: *code_hash,
+            abi,
+            ..self
+        }
+    }
+
+    /// Returns a copy of this contract with another owner.
+    pub fn set_owner(self, owner: &PublicKey) -> Self {
+        Self {
+            owner: *owner,
+            ..self
+        }
+    }
+}
+
+/// Single entry of the contract state.
+#[derive(Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::StateEntry", serde_pb_convert)]
+pub struct StateEntry {
+    pub key: String,
+    pub value: Value,
+}
+
+impl StateEntry {
+    pub fn new(key: &str, value: Value) -> Self {
+        Self {
+            key: key.to_string(),
+            value,
         }
     }
 }
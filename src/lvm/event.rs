@@ -0,0 +1,26 @@
+use exonum::crypto::{Hash, PublicKey};
+
+use super::{proto, value::Value};
+
+/// Event emitted by a contract with the `emit` host function.
+#[derive(Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::Event", serde_pb_convert)]
+pub struct Event {
+    /// `PublicKey` of the emitting contract.
+    pub contract: PublicKey,
+    /// `Hash` of the transaction which emitted the event.
+    pub tx_hash: Hash,
+    pub name: String,
+    pub data: Value,
+}
+
+impl Event {
+    pub fn new(contract: &PublicKey, tx_hash: &Hash, name: &str, data: Value) -> Self {
+        Self {
+            contract: *contract,
+            tx_hash: *tx_hash,
+            name: name.to_string(),
+            data,
+        }
+    }
+}
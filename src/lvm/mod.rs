@@ -1,9 +1,15 @@
+pub mod abi;
 pub mod api;
+pub mod config;
 pub mod contract;
+pub mod event;
+pub mod receipt;
 pub mod schema;
 pub mod transactions;
 pub mod service;
 pub mod runner;
+pub mod token;
+pub mod value;
 
 pub use schema::Schema;
 pub use service::{Service, ServiceFactory};
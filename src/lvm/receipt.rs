@@ -0,0 +1,24 @@
+use exonum::crypto::Hash;
+
+use super::{proto, value::Value};
+
+/// Outcome of a successful contract call, stored by transaction hash.
+#[derive(Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::Receipt", serde_pb_convert)]
+pub struct Receipt {
+    pub gas_used: u64,
+    /// Values returned by the called function.
+    pub returns: Vec<Value>,
+    /// `Hash` of the events emitted by the transaction, anchors them in the state.
+    pub events_hash: Hash,
+}
+
+impl Receipt {
+    pub fn new(gas_used: u64, returns: Vec<Value>, events_hash: &Hash) -> Self {
+        Self {
+            gas_used,
+            returns,
+            events_hash: *events_hash,
+        }
+    }
+}
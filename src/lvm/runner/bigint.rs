@@ -0,0 +1,136 @@
+use rlua::{Context, FromLua, MetaMethod, UserData, UserDataMethods, Value as LuaValue};
+
+use super::lua_api::HostError;
+
+/// Integer with checked arithmetic for money math, available to Lua as `bigint(x)`.
+///
+/// Operations which overflow or divide by zero raise a Lua error instead of wrapping
+/// or turning into floats, division rounds down. Other operands may be integers,
+/// integral floats, decimal strings or bigints.
+///
+/// Lua calls `__eq` only when both values are bigints, so `bigint(0) == 0` is `false`.
+/// `bigint(0):eq(0)` compares with other operands as well.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct BigInt(pub i128);
+
+impl BigInt {
+    pub fn to_i64(self) -> Option<i64> {
+        if self.0 >= i128::from(i64::min_value()) && self.0 <= i128::from(i64::max_value()) {
+            Some(self.0 as i64)
+        } else {
+            None
+        }
+    }
+}
+
+impl UserData for BigInt {
+    fn add_methods<'lua, M: UserDataMethods<'lua, Self>>(methods: &mut M) {
+        methods.add_meta_function(MetaMethod::Add, |_, (a, b): (Operand, Operand)| {
+            checked(a.0.checked_add(b.0))
+        });
+        methods.add_meta_function(MetaMethod::Sub, |_, (a, b): (Operand, Operand)| {
+            checked(a.0.checked_sub(b.0))
+        });
+        methods.add_meta_function(MetaMethod::Mul, |_, (a, b): (Operand, Operand)| {
+            checked(a.0.checked_mul(b.0))
+        });
+        methods.add_meta_function(MetaMethod::Div, |_, (a, b): (Operand, Operand)| {
+            floor_div(a.0, b.0)
+        });
+        methods.add_meta_function(MetaMethod::IDiv, |_, (a, b): (Operand, Operand)| {
+            floor_div(a.0, b.0)
+        });
+        methods.add_meta_function(MetaMethod::Mod, |_, (a, b): (Operand, Operand)| {
+            floor_mod(a.0, b.0)
+        });
+        methods.add_meta_function(MetaMethod::Unm, |_, a: Operand| checked(a.0.checked_neg()));
+        methods.add_meta_function(MetaMethod::Eq, |_, (a, b): (Operand, Operand)| Ok(a.0 == b.0));
+        methods.add_meta_function(MetaMethod::Lt, |_, (a, b): (Operand, Operand)| Ok(a.0 < b.0));
+        methods.add_meta_function(MetaMethod::Le, |_, (a, b): (Operand, Operand)| Ok(a.0 <= b.0));
+        methods.add_meta_method(MetaMethod::ToString, |_, this, ()| Ok(this.0.to_string()));
+
+        methods.add_method("eq", |_, this, other: Operand| Ok(this.0 == other.0));
+        methods.add_method("tointeger", |_, this, ()| {
+            this.to_i64().ok_or_else(|| {
+                rlua::Error::RuntimeError("bigint does not fit into an integer".to_string())
+            })
+        });
+    }
+}
+
+/// Registers the `bigint` constructor.
+pub fn register(lua_ctx: Context) -> rlua::Result<()> {
+    let constructor = lua_ctx.create_function(|_, value: Operand| Ok(BigInt(value.0)))?;
+    lua_ctx.globals().raw_set("bigint", constructor)
+}
+
+/// Operand of a bigint operation.
+struct Operand(i128);
+
+impl<'lua> FromLua<'lua> for Operand {
+    fn from_lua(value: LuaValue<'lua>, _: Context<'lua>) -> rlua::Result<Self> {
+        let type_name = value.type_name();
+        let operand = match value {
+            LuaValue::Integer(i) => Some(i128::from(i)),
+            LuaValue::Number(n) if n.fract() == 0.0 && n.abs() < 2f64.powi(127) => {
+                Some(n as i128)
+            }
+            LuaValue::String(s) => s.to_str()?.parse().ok(),
+            LuaValue::UserData(ud) => ud.borrow::<BigInt>().ok().map(|b| b.0),
+            _ => None,
+        };
+        operand.map(Operand).ok_or_else(|| rlua::Error::FromLuaConversionError {
+            from: type_name,
+            to: "bigint",
+            message: Some("expected an integer".to_string()),
+        })
+    }
+}
+
+/// Currency amount passed to host functions: a non-negative integer fitting into `u64`.
+pub struct Amount(pub u64);
+
+impl<'lua> FromLua<'lua> for Amount {
+    fn from_lua(value: LuaValue<'lua>, lua_ctx: Context<'lua>) -> rlua::Result<Self> {
+        let invalid = || rlua::Error::external(HostError::InvalidAmount);
+        let operand = Operand::from_lua(value, lua_ctx).map_err(|_| invalid())?;
+        if operand.0 < 0 || operand.0 > i128::from(u64::max_value()) {
+            return Err(invalid());
+        }
+        Ok(Amount(operand.0 as u64))
+    }
+}
+
+fn checked(result: Option<i128>) -> rlua::Result<BigInt> {
+    result
+        .map(BigInt)
+        .ok_or_else(|| rlua::Error::RuntimeError("bigint overflow".to_string()))
+}
+
+fn floor_div(a: i128, b: i128) -> rlua::Result<BigInt> {
+    if b == 0 {
+        return Err(rlua::Error::RuntimeError(
+            "attempt to divide by zero".to_string(),
+        ));
+    }
+    let quotient = checked(a.checked_div(b))?.0;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        Ok(BigInt(quotient - 1))
+    } else {
+        Ok(BigInt(quotient))
+    }
+}
+
+fn floor_mod(a: i128, b: i128) -> rlua::Result<BigInt> {
+    if b == 0 {
+        return Err(rlua::Error::RuntimeError(
+            "attempt to perform 'n%0'".to_string(),
+        ));
+    }
+    let remainder = a.checked_rem(b).unwrap_or(0);
+    if remainder != 0 && (remainder < 0) != (b < 0) {
+        Ok(BigInt(remainder + b))
+    } else {
+        Ok(BigInt(remainder))
+    }
+}
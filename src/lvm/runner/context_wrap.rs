@@ -1,78 +1,559 @@
-use exonum::{blockchain::TransactionContext, crypto::PublicKey};
+use exonum::{
+    crypto::{Hash, PublicKey},
+    storage::Fork,
+};
 
-use rlua::Context;
+use rlua::{Context, Lua, Scope, Table, Value as LuaValue, Variadic};
 
-use std::sync::Mutex;
+use std::{cell::RefCell, collections::BTreeSet};
 
 use crate::{
     currency::{schema::Schema as CurrencySchema, wallet::Wallet},
-    lvm::contract::Contract,
+    lvm::{event::Event, schema::Schema as LvmSchema, value::Value},
 };
 
-use super::{lua_api::CurrencyApi, runner::Runner};
+use super::{
+    bigint::Amount,
+    gas::{GasMeter, STORAGE_GAS_PER_BYTE},
+    lua_api::{ContractApi, CurrencyApi, EventApi, HostError, TokenApi},
+    runner::{Error, Runner},
+};
 
-static mut WRAP: Option<Mutex<RunnerCtxWrap>> = None;
+/// Maximum number of contracts in a chain of nested calls, including the called one.
+pub const MAX_CALL_DEPTH: usize = 8;
+/// Registry key of the table with state entries loaded or written during the call.
+const STATE_CACHE: &str = "lvm.state_cache";
+/// Gas charged for every read or write of a state entry, on top of the charge per byte.
+const STATE_ACCESS_GAS: u64 = 100;
+/// Maximum size of a state key in bytes.
+pub const MAX_STATE_KEY_SIZE: usize = 256;
+/// Maximum size of a state value in its binary representation.
+pub const MAX_STATE_VALUE_SIZE: u64 = 64 * 1024;
+/// Gas charged for every nested call, on top of the charge per byte of the called code.
+const CALL_GAS: u64 = 1000;
+/// Gas charged for every emitted event, on top of the charge per byte.
+const EMIT_GAS: u64 = 100;
+/// Maximum size of an event name and data in their binary representation.
+pub const MAX_EVENT_SIZE: u64 = 4 * 1024;
+/// Maximum number of events emitted by a transaction, including nested calls.
+pub const MAX_TX_EVENTS: u64 = 256;
 
-pub struct RunnerCtxWrap {
-    _contract: *mut Contract,
-    contract_wallet: *mut Wallet,
-    context: *mut TransactionContext<'static>,
+/// Host side of a single contract call.
+///
+/// Lua callbacks borrow the wrap through `Context::scope`, so they cannot outlive
+/// the call and each runner owns its own fork.
+pub struct RunnerCtxWrap<'a> {
+    lua: &'a Lua,
+    contract_wallet: Wallet,
+    fork: RefCell<&'a mut Fork>,
+    tx_hash: Option<Hash>,
+    touched_keys: RefCell<BTreeSet<String>>,
+    events: RefCell<Vec<Event>>,
+    gas: GasMeter,
+    /// Memory available to this call and the calls it makes.
+    memory_limit: usize,
+    /// Contracts up the call chain, ending with the current one.
+    call_stack: Vec<PublicKey>,
+    call_error: RefCell<Option<Error>>,
 }
 
-unsafe impl Send for RunnerCtxWrap {}
+impl<'a> RunnerCtxWrap<'a> {
+    pub fn new(
+        lua: &'a Lua,
+        contract_wallet: Wallet,
+        fork: &'a mut Fork,
+        tx_hash: Option<Hash>,
+        gas: GasMeter,
+        memory_limit: usize,
+        call_stack: Vec<PublicKey>,
+    ) -> Self {
+        Self {
+            lua,
+            contract_wallet,
+            fork: RefCell::new(fork),
+            tx_hash,
+            touched_keys: RefCell::new(BTreeSet::new()),
+            events: RefCell::new(Vec::new()),
+            gas,
+            memory_limit,
+            call_stack,
+            call_error: RefCell::new(None),
+        }
+    }
+
+    /// Sets up the `state` global, which loads contract state entries on first access.
+    ///
+    /// Entries are cached for the rest of the call, so nested tables can be modified
+    /// in place. The state cannot be iterated with `pairs`.
+    ///
+    /// Every access costs gas for the key, and the first read also for the loaded value.
+    /// Written values are charged for once the call ends, see `state_changes`.
+    pub fn register_state<'lua, 'scope>(
+        &'scope self,
+        lua_ctx: &Context<'lua>,
+        scope: &Scope<'lua, 'scope>,
+    ) -> rlua::Result<()> {
+        lua_ctx.set_named_registry_value(STATE_CACHE, lua_ctx.create_table()?)?;
+
+        let index_fn = scope.create_function(move |ctx, (_, key): (LuaValue, LuaValue)| {
+            let key = lua_state_key(key)?;
+            self.gas.charge(STATE_ACCESS_GAS + key.len() as u64 * STORAGE_GAS_PER_BYTE)?;
+            let cache: Table = ctx.named_registry_value(STATE_CACHE)?;
+            if !self.touched_keys.borrow().contains(&key) {
+                let value = self.load_state(&key);
+                let size = value.as_ref().map_or(0, Value::byte_size);
+                self.gas.charge(size * STORAGE_GAS_PER_BYTE)?;
+                cache.raw_set(key.as_str(), value)?;
+                self.touched_keys.borrow_mut().insert(key.clone());
+            }
+            cache.raw_get::<_, LuaValue>(key)
+        })?;
 
-impl RunnerCtxWrap {
-    pub fn init(runner: &mut Runner) {
-        unsafe fn extend_lifetime<'a, 'ctx>(
-            r: &mut TransactionContext<'ctx>,
-        ) -> &'a mut TransactionContext<'static> {
-            std::mem::transmute::<_, _>(r)
+        let newindex_fn = scope.create_function(
+            move |ctx, (_, key, value): (LuaValue, LuaValue, LuaValue)| {
+                if self.tx_hash.is_none() {
+                    return Err(rlua::Error::external(HostError::ReadOnly));
+                }
+                let key = lua_state_key(key)?;
+                self.gas.charge(STATE_ACCESS_GAS + key.len() as u64 * STORAGE_GAS_PER_BYTE)?;
+                let cache: Table = ctx.named_registry_value(STATE_CACHE)?;
+                cache.raw_set(key.as_str(), value)?;
+                self.touched_keys.borrow_mut().insert(key);
+                Ok(())
+            },
+        )?;
+
+        let meta = lua_ctx.create_table()?;
+        meta.raw_set("__index", index_fn)?;
+        meta.raw_set("__newindex", newindex_fn)?;
+        meta.raw_set("__metatable", false)?;
+
+        let state = lua_ctx.create_table()?;
+        state.set_metatable(Some(meta));
+        lua_ctx.globals().raw_set("state", state)
+    }
+
+    /// Returns state entries which differ from the stored ones, charging for their size.
+    pub fn state_changes(&self, lua_ctx: &Context) -> rlua::Result<Vec<(String, Value)>> {
+        let cache: Table = lua_ctx.named_registry_value(STATE_CACHE)?;
+
+        let mut changes = Vec::new();
+        for key in self.touched_keys.borrow().iter() {
+            let value: Value = cache.raw_get(key.as_str())?;
+            if self.load_state(key).unwrap_or(Value::Nil) != value {
+                let size = value.byte_size();
+                if size > MAX_STATE_VALUE_SIZE {
+                    return Err(rlua::Error::external(HostError::ValueTooLarge));
+                }
+                self.gas.charge(size * STORAGE_GAS_PER_BYTE)?;
+                changes.push((key.clone(), value));
+            }
         }
+        Ok(changes)
+    }
 
-        unsafe {
-            WRAP = Some(Mutex::new(RunnerCtxWrap {
-                _contract: &mut runner.contract,
-                contract_wallet: &mut runner.contract_wallet,
-                context: extend_lifetime(runner.context),
-            }));
-        };
+    /// Returns events emitted during the call.
+    pub fn take_events(&self) -> Vec<Event> {
+        self.events.replace(Vec::new())
+    }
+
+    /// Returns the error of a failed nested call, if any.
+    ///
+    /// Such errors fail the whole call even if the contract caught them with `pcall`.
+    pub fn take_call_error(&self) -> Option<Error> {
+        self.call_error.borrow_mut().take()
     }
 
-    pub fn reset() {
-        unsafe { WRAP = None };
+    fn load_state(&self, key: &str) -> Option<Value> {
+        let fork = self.fork.borrow();
+        LvmSchema::new(&**fork).state_value(&self.contract_wallet.pub_key, key)
     }
 
-    pub fn register_functions(lua_ctx: &Context) -> rlua::Result<()> {
+    pub fn register_functions<'lua, 'scope>(
+        &'scope self,
+        lua_ctx: &Context<'lua>,
+        scope: &Scope<'lua, 'scope>,
+    ) -> rlua::Result<()> {
         let globals = lua_ctx.globals();
 
-        let transfer_fn = lua_ctx.create_function(|_, (to, amount): (String, u64)| {
-            RunnerCtxWrap::transfer(&to, amount);
-            Ok(())
+        let transfer_fn = scope.create_function(move |_, (to, amount): (String, Amount)| {
+            self.transfer(&to, amount.0).map_err(rlua::Error::external)
         })?;
         globals.raw_set("transfer", transfer_fn)?;
 
+        let balance_fn = scope.create_function(move |_, owner: String| {
+            self.balance(&owner).map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("balance", balance_fn)?;
+
+        let self_balance_fn = scope.create_function(move |_, ()| Ok(self.self_balance()))?;
+        globals.raw_set("self_balance", self_balance_fn)?;
+
+        let emit_fn = scope.create_function(move |_, (name, data): (String, Value)| {
+            let size = name.len() as u64 + data.byte_size();
+            if size > MAX_EVENT_SIZE {
+                return Err(rlua::Error::external(HostError::EventTooLarge));
+            }
+            // Events are stored on chain, so they are charged for like state values.
+            self.gas.charge(EMIT_GAS + size * STORAGE_GAS_PER_BYTE)?;
+            self.emit(&name, data).map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("emit", emit_fn)?;
+
+        let call_fn = scope.create_function(
+            move |lua_ctx, (contract, fn_name, args): (String, String, Variadic<Value>)| {
+                // Garbage of the caller would otherwise reduce memory available to the callee.
+                self.gas.charge_memory(lua_ctx)?;
+                self.gas.collect_garbage(lua_ctx)?;
+                self.call(&contract, &fn_name, args.into_iter().collect())
+                    .map(|returns| returns.into_iter().collect::<Variadic<_>>())
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("call", call_fn)?;
+
+        self.register_token_functions(lua_ctx, scope)
+    }
+
+    fn register_token_functions<'lua, 'scope>(
+        &'scope self,
+        lua_ctx: &Context<'lua>,
+        scope: &Scope<'lua, 'scope>,
+    ) -> rlua::Result<()> {
+        let globals = lua_ctx.globals();
+
+        let create_fn = scope.create_function(
+            move |_, (name, symbol, decimals): (String, String, u32)| {
+                self.token_create(&name, &symbol, decimals)
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("token_create", create_fn)?;
+
+        let mint_fn = scope.create_function(move |_, (to, amount): (String, Amount)| {
+            self.token_mint(&to, amount.0).map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("token_mint", mint_fn)?;
+
+        let burn_fn = scope.create_function(move |_, amount: Amount| {
+            self.token_burn(amount.0).map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("token_burn", burn_fn)?;
+
+        let transfer_fn = scope.create_function(
+            move |_, (token, to, amount): (String, String, Amount)| {
+                self.token_transfer(&token, &to, amount.0)
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("token_transfer", transfer_fn)?;
+
+        let approve_fn = scope.create_function(
+            move |_, (token, spender, amount): (String, String, Amount)| {
+                self.token_approve(&token, &spender, amount.0)
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("token_approve", approve_fn)?;
+
+        let transfer_from_fn = scope.create_function(
+            move |_, (token, from, to, amount): (String, String, String, Amount)| {
+                self.token_transfer_from(&token, &from, &to, amount.0)
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("token_transfer_from", transfer_from_fn)?;
+
+        let balance_fn = scope.create_function(move |_, (token, holder): (String, String)| {
+            self.token_balance(&token, &holder)
+                .map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("token_balance", balance_fn)?;
+
+        let allowance_fn = scope.create_function(
+            move |_, (token, owner, spender): (String, String, String)| {
+                self.token_allowance(&token, &owner, &spender)
+                    .map_err(rlua::Error::external)
+            },
+        )?;
+        globals.raw_set("token_allowance", allowance_fn)?;
+
+        let supply_fn = scope.create_function(move |_, token: String| {
+            self.token_supply(&token).map_err(rlua::Error::external)
+        })?;
+        globals.raw_set("token_supply", supply_fn)?;
+
         Ok(())
     }
 }
 
-impl CurrencyApi for RunnerCtxWrap {
-    fn transfer(receiver: &String, amount: u64) {
-        if let Some(wrap) = unsafe { &WRAP } {
-            let mut wrap = wrap.lock().unwrap();
+impl ContractApi for RunnerCtxWrap<'_> {
+    fn call(&self, contract: &str, fn_name: &str, args: Vec<Value>) -> Result<Vec<Value>, HostError> {
+        let contract = parse_key(contract)?;
+        if self.call_stack.contains(&contract) {
+            return Err(HostError::Reentrancy);
+        }
+        if self.call_stack.len() >= MAX_CALL_DEPTH {
+            return Err(HostError::CallDepthExceeded);
+        }
+
+        let mut fork = self.fork.borrow_mut();
+        let (contract, contract_wallet, code_size) = {
+            let lvm_schema = LvmSchema::new(&**fork);
+            let currency_schema = CurrencySchema::new(&**fork);
+            match (lvm_schema.contract(&contract), currency_schema.wallet(&contract)) {
+                (Some(contract), Some(wallet)) => {
+                    let code_size = lvm_schema.code(&contract.code_hash).map_or(0, |c| c.len());
+                    (contract, wallet, code_size as u64)
+                }
+                _ => return Err(HostError::UnknownContract),
+            }
+        };
+
+        // Setting up a VM and loading the called code are paid for by the caller.
+        if self
+            .gas
+            .charge(CALL_GAS + code_size * STORAGE_GAS_PER_BYTE)
+            .is_err()
+        {
+            *self.call_error.borrow_mut() = Some(Error::OutOfGas);
+            return Err(HostError::CallFailed);
+        }
 
-            let sender = unsafe { &*wrap.contract_wallet };
-            let context = unsafe { &mut *wrap.context };
+        let runner = Runner {
+            contract,
+            contract_wallet,
+            fork: &mut **fork,
+            caller: self.contract_wallet.pub_key,
+            tx_hash: self.tx_hash,
+            value: 0,
+            gas_limit: self.gas.remaining(),
+            // Memory held by the callers stays allocated during the call.
+            memory_limit: self.memory_limit.saturating_sub(self.lua.used_memory()),
+            call_stack: self.call_stack.clone(),
+        };
 
-            let tx_hash = context.tx_hash();
-            let mut schema = CurrencySchema::new(context.fork());
+        let execution = match runner.exec(fn_name, args) {
+            Ok(execution) => execution,
+            Err(failure) => {
+                // Gas burnt by the failed call is paid by the caller as well.
+                let _ = self.gas.charge(failure.gas_used);
+                *self.call_error.borrow_mut() = Some(failure.error);
+                return Err(HostError::CallFailed);
+            }
+        };
+        // The nested call was limited by the remaining gas, so this cannot exceed the limit.
+        let _ = self.gas.charge(execution.gas_used);
 
-            // TODO: handle errors
-            let receiver = hex::decode(&receiver).unwrap();
-            let receiver = PublicKey::from_slice(&receiver).unwrap();
-            let receiver = schema.wallet(&receiver).unwrap();
+        // Changes of the called contract are applied right away, so that following calls see them.
+        let mut schema = LvmSchema::new(&mut **fork);
+        let contract = schema.update_contract_state(execution.contract, execution.state_changes);
+        if let Some(tx_hash) = self.tx_hash {
+            schema.append_contract_events(contract, &tx_hash, execution.events);
+        }
+        Ok(execution.returns)
+    }
+}
+
+impl EventApi for RunnerCtxWrap<'_> {
+    fn emit(&self, name: &str, data: Value) -> Result<(), HostError> {
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        // Events of finished nested calls are already stored.
+        let stored = {
+            let fork = self.fork.borrow();
+            LvmSchema::new(&**fork).tx_events(&tx_hash).len()
+        };
+        if stored + self.events.borrow().len() as u64 >= MAX_TX_EVENTS {
+            return Err(HostError::TooManyEvents);
+        }
+        let event = Event::new(&self.contract_wallet.pub_key, &tx_hash, name, data);
+        self.events.borrow_mut().push(event);
+        Ok(())
+    }
+}
+
+impl CurrencyApi for RunnerCtxWrap<'_> {
+    fn transfer(&self, receiver: &str, amount: u64) -> Result<(), HostError> {
+        let receiver = parse_key(receiver)?;
+
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+
+        let mut fork = self.fork.borrow_mut();
+        let mut schema = CurrencySchema::new(&mut **fork);
+
+        if schema.wallet(&receiver).is_none() {
+            return Err(HostError::UnknownReceiver);
+        }
+
+        let sender = schema
+            .wallet(&self.contract_wallet.pub_key)
+            .ok_or(HostError::InsufficientFunds)?;
+        if sender.balance < amount {
+            return Err(HostError::InsufficientFunds);
+        }
+        schema.decrease_wallet_balance(sender, amount, &tx_hash);
+
+        let receiver = schema.wallet(&receiver).ok_or(HostError::UnknownReceiver)?;
+        schema.increase_wallet_balance(receiver, amount, &tx_hash);
+
+        Ok(())
+    }
+
+    fn balance(&self, owner: &str) -> Result<Option<u64>, HostError> {
+        let owner = parse_key(owner)?;
+        let fork = self.fork.borrow();
+        let schema = CurrencySchema::new(&**fork);
+        Ok(schema.wallet(&owner).map(|wallet| wallet.balance))
+    }
+
+    fn self_balance(&self) -> u64 {
+        let fork = self.fork.borrow();
+        let schema = CurrencySchema::new(&**fork);
+        // The contract wallet is created together with the contract and never removed.
+        schema
+            .wallet(&self.contract_wallet.pub_key)
+            .map_or(0, |wallet| wallet.balance)
+    }
+}
+
+impl TokenApi for RunnerCtxWrap<'_> {
+    fn token_create(&self, name: &str, symbol: &str, decimals: u32) -> Result<(), HostError> {
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).create_token(
+            &self.contract_wallet.pub_key,
+            name,
+            symbol,
+            decimals,
+            &tx_hash,
+        )?;
+        Ok(())
+    }
+
+    fn token_mint(&self, to: &str, amount: u64) -> Result<(), HostError> {
+        let to = parse_key(to)?;
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).mint_tokens(
+            &self.contract_wallet.pub_key,
+            &to,
+            amount,
+            &tx_hash,
+        )?;
+        Ok(())
+    }
+
+    fn token_burn(&self, amount: u64) -> Result<(), HostError> {
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let own_key = &self.contract_wallet.pub_key;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).burn_tokens(own_key, own_key, amount, &tx_hash)?;
+        Ok(())
+    }
+
+    fn token_transfer(&self, token: &str, to: &str, amount: u64) -> Result<(), HostError> {
+        let token = parse_key(token)?;
+        let to = parse_key(to)?;
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).transfer_tokens(
+            &token,
+            &self.contract_wallet.pub_key,
+            &to,
+            amount,
+            &tx_hash,
+        )?;
+        Ok(())
+    }
+
+    fn token_approve(&self, token: &str, spender: &str, amount: u64) -> Result<(), HostError> {
+        let token = parse_key(token)?;
+        let spender = parse_key(spender)?;
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).approve_tokens(
+            &token,
+            &self.contract_wallet.pub_key,
+            &spender,
+            amount,
+            &tx_hash,
+        )?;
+        Ok(())
+    }
+
+    fn token_transfer_from(
+        &self,
+        token: &str,
+        from: &str,
+        to: &str,
+        amount: u64,
+    ) -> Result<(), HostError> {
+        let token = parse_key(token)?;
+        let from = parse_key(from)?;
+        let to = parse_key(to)?;
+        let tx_hash = self.tx_hash.ok_or(HostError::ReadOnly)?;
+        let mut fork = self.fork.borrow_mut();
+        LvmSchema::new(&mut **fork).transfer_tokens_from(
+            &token,
+            &self.contract_wallet.pub_key,
+            &from,
+            &to,
+            amount,
+            &tx_hash,
+        )?;
+        Ok(())
+    }
+
+    fn token_balance(&self, token: &str, holder: &str) -> Result<Option<u64>, HostError> {
+        let token = parse_key(token)?;
+        let holder = parse_key(holder)?;
+        let fork = self.fork.borrow();
+        let schema = LvmSchema::new(&**fork);
+        Ok(schema
+            .token(&token)
+            .map(|_| schema.token_balance(&token, &holder)))
+    }
+
+    fn token_allowance(
+        &self,
+        token: &str,
+        owner: &str,
+        spender: &str,
+    ) -> Result<Option<u64>, HostError> {
+        let token = parse_key(token)?;
+        let owner = parse_key(owner)?;
+        let spender = parse_key(spender)?;
+        let fork = self.fork.borrow();
+        let schema = LvmSchema::new(&**fork);
+        Ok(schema
+            .token(&token)
+            .map(|_| schema.token_allowance(&token, &owner, &spender)))
+    }
+
+    fn token_supply(&self, token: &str) -> Result<Option<u64>, HostError> {
+        let token = parse_key(token)?;
+        let fork = self.fork.borrow();
+        Ok(LvmSchema::new(&**fork)
+            .token(&token)
+            .map(|token| token.total_supply))
+    }
+}
+
+fn parse_key(key: &str) -> Result<PublicKey, HostError> {
+    hex::decode(key)
+        .ok()
+        .and_then(|bytes| PublicKey::from_slice(&bytes))
+        .ok_or(HostError::MalformedKey)
+}
 
-            schema.decrease_wallet_balance(sender.clone(), amount, &tx_hash);
-            schema.increase_wallet_balance(receiver, amount, &tx_hash);
+fn lua_state_key(key: LuaValue) -> rlua::Result<String> {
+    match key {
+        LuaValue::String(ref s) if s.as_bytes().len() > MAX_STATE_KEY_SIZE => {
+            Err(rlua::Error::external(HostError::KeyTooLarge))
         }
+        LuaValue::String(s) => Ok(s.to_str()?.to_owned()),
+        _ => Err(rlua::Error::RuntimeError(
+            "state keys must be strings".to_string(),
+        )),
     }
 }
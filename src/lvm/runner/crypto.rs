@@ -0,0 +1,70 @@
+use exonum::crypto::{self, PublicKey, Signature};
+
+use rlua::{Context, String as LuaString};
+
+use super::{gas::GasMeter, lua_api::HostError};
+
+/// Maximum size of data passed to a single cryptographic function.
+pub const MAX_INPUT_SIZE: usize = 64 * 1024;
+
+/// Gas charged for each processed byte on top of the base cost of the function.
+const GAS_PER_BYTE: u64 = 1;
+const HASH_GAS: u64 = 100;
+const VERIFY_GAS: u64 = 1_000;
+
+/// Registers `sha256`, `verify_signature`, `hex_encode` and `hex_decode`.
+///
+/// Hashes, keys and signatures are passed as hex strings, data as raw Lua strings.
+pub fn register(lua_ctx: Context, gas: &GasMeter) -> rlua::Result<()> {
+    let globals = lua_ctx.globals();
+
+    let meter = gas.clone();
+    let sha256 = lua_ctx.create_function(move |_, data: LuaString| {
+        let data = checked_input(&meter, HASH_GAS, data.as_bytes())?;
+        Ok(hex::encode(crypto::hash(data).as_ref()))
+    })?;
+    globals.raw_set("sha256", sha256)?;
+
+    let meter = gas.clone();
+    let verify_signature = lua_ctx.create_function(
+        move |_, (pub_key, data, signature): (LuaString, LuaString, LuaString)| {
+            let data = checked_input(&meter, VERIFY_GAS, data.as_bytes())?;
+            let pub_key = hex::decode(pub_key.as_bytes())
+                .ok()
+                .and_then(|bytes| PublicKey::from_slice(&bytes))
+                .ok_or_else(|| rlua::Error::external(HostError::MalformedKey))?;
+            let signature = hex::decode(signature.as_bytes())
+                .ok()
+                .and_then(|bytes| Signature::from_slice(&bytes))
+                .ok_or_else(|| rlua::Error::external(HostError::MalformedSignature))?;
+            Ok(crypto::verify(&signature, data, &pub_key))
+        },
+    )?;
+    globals.raw_set("verify_signature", verify_signature)?;
+
+    let meter = gas.clone();
+    let hex_encode = lua_ctx.create_function(move |_, data: LuaString| {
+        let data = checked_input(&meter, 0, data.as_bytes())?;
+        Ok(hex::encode(data))
+    })?;
+    globals.raw_set("hex_encode", hex_encode)?;
+
+    let meter = gas.clone();
+    let hex_decode = lua_ctx.create_function(move |lua_ctx, data: LuaString| {
+        let data = checked_input(&meter, 0, data.as_bytes())?;
+        let bytes = hex::decode(data).map_err(|_| rlua::Error::external(HostError::MalformedHex))?;
+        lua_ctx.create_string(&bytes)
+    })?;
+    globals.raw_set("hex_decode", hex_decode)?;
+
+    Ok(())
+}
+
+/// Checks the input size and charges gas for processing it.
+fn checked_input<'a>(gas: &GasMeter, base_cost: u64, data: &'a [u8]) -> rlua::Result<&'a [u8]> {
+    if data.len() > MAX_INPUT_SIZE {
+        return Err(rlua::Error::external(HostError::InputTooLarge));
+    }
+    gas.charge(base_cost + GAS_PER_BYTE * data.len() as u64)?;
+    Ok(data)
+}
@@ -0,0 +1,242 @@
+use exonum::crypto::{self, Hash, PublicKey};
+
+use rlua::{Context, MultiValue, Table, Value as LuaValue};
+
+use std::cell::Cell;
+
+/// Globals removed from the Lua environment, as they access the node or are not deterministic.
+const FORBIDDEN_GLOBALS: &[&str] = &["dofile", "loadfile", "collectgarbage", "print"];
+
+/// Type names printed by Lua together with the heap address of a value.
+const REFERENCE_TYPES: &[&str] = &["table", "function", "userdata", "thread"];
+
+/// Restricts `load` to text chunks, precompiled bytecode can break the VM.
+///
+/// Heap addresses and the order of hash table keys differ between nodes, so `tostring`
+/// prints reference values with ids in the order they are first printed, and `next`
+/// and `pairs` iterate over keys in sorted order: numbers, then strings, then booleans.
+/// Tables with keys of other types cannot be iterated.
+const SANDBOX_PRELUDE: &str = r##"
+    local raw_load = load
+    load = function(chunk, chunkname, _, ...)
+        if select("#", ...) > 0 then
+            return raw_load(chunk, chunkname, "t", ...)
+        end
+        return raw_load(chunk, chunkname, "t")
+    end
+
+    local raw_tostring, raw_format, raw_next = tostring, string.format, next
+    local error, getmetatable, rawget, setmetatable, type = error, getmetatable, rawget, setmetatable, type
+    local find, sub, pack, sort, unpack = string.find, string.sub, table.pack, table.sort, table.unpack
+
+    local is_reference = { table = true, ["function"] = true, userdata = true, thread = true }
+    local ids = setmetatable({}, { __mode = "k" })
+    local last_id = 0
+
+    local function deterministic_tostring(...)
+        local value = ...
+        local text = raw_tostring(...)
+        if is_reference[type(value)] then
+            local start = find(text, ": 0x%x+$")
+            if start then
+                local id = ids[value]
+                if id == nil then
+                    last_id = last_id + 1
+                    id = last_id
+                    ids[value] = id
+                end
+                text = sub(text, 1, start + 1) .. id
+            end
+        end
+        return text
+    end
+    tostring = deterministic_tostring
+
+    string.format = function(format, ...)
+        local args = pack(...)
+        for i = 1, args.n do
+            if is_reference[type(args[i])] then
+                args[i] = deterministic_tostring(args[i])
+            end
+        end
+        return raw_format(format, unpack(args, 1, args.n))
+    end
+
+    local key_ranks = { number = 1, string = 2, boolean = 3 }
+    local function key_less(a, b)
+        local rank_a, rank_b = key_ranks[type(a)], key_ranks[type(b)]
+        if rank_a ~= rank_b then
+            return rank_a < rank_b
+        end
+        if rank_a == 3 then
+            return b and not a
+        end
+        return a < b
+    end
+
+    -- Sorted keys of the tables being iterated, taken when the iteration starts.
+    local snapshots = setmetatable({}, { __mode = "k" })
+    local function snapshot(t)
+        local keys, positions, n = {}, {}, 0
+        local key = raw_next(t)
+        while key ~= nil do
+            if key_ranks[type(key)] == nil then
+                error("cannot iterate over a table with " .. type(key) .. " keys", 3)
+            end
+            n = n + 1
+            keys[n] = key
+            key = raw_next(t, key)
+        end
+        sort(keys, key_less)
+        for i = 1, n do
+            positions[keys[i]] = i
+        end
+        local keys_snapshot = { keys = keys, positions = positions }
+        snapshots[t] = keys_snapshot
+        return keys_snapshot
+    end
+
+    local function sorted_next(t, key)
+        if type(t) ~= "table" then
+            error("bad argument #1 to 'next' (table expected, got " .. type(t) .. ")", 2)
+        end
+        local keys_snapshot, i
+        if key == nil then
+            keys_snapshot, i = snapshot(t), 0
+        else
+            keys_snapshot = snapshots[t]
+            i = keys_snapshot and keys_snapshot.positions[key]
+            if i == nil then
+                keys_snapshot = snapshot(t)
+                i = keys_snapshot.positions[key]
+                if i == nil then
+                    error("invalid key to 'next'", 2)
+                end
+            end
+        end
+        -- Entries removed during the iteration are skipped.
+        while true do
+            i = i + 1
+            local next_key = keys_snapshot.keys[i]
+            if next_key == nil then
+                return nil
+            end
+            local value = rawget(t, next_key)
+            if value ~= nil then
+                return next_key, value
+            end
+        end
+    end
+    next = sorted_next
+
+    pairs = function(t)
+        local meta = getmetatable(t)
+        if type(meta) == "table" and rawget(meta, "__pairs") ~= nil then
+            local iterator, state, control = rawget(meta, "__pairs")(t)
+            return iterator, state, control
+        end
+        if type(t) ~= "table" then
+            error("bad argument #1 to 'pairs' (table expected, got " .. type(t) .. ")", 2)
+        end
+        return sorted_next, t, nil
+    end
+"##;
+
+/// Wraps `table` into a proxy which can be read but not modified from Lua.
+pub fn read_only<'lua>(lua_ctx: Context<'lua>, table: Table<'lua>) -> rlua::Result<Table<'lua>> {
+    let deny_write = lua_ctx.create_function(|_, _: MultiValue| -> rlua::Result<()> {
+        Err(rlua::Error::RuntimeError(
+            "attempt to modify a read-only table".to_string(),
+        ))
+    })?;
+
+    let meta = lua_ctx.create_table()?;
+    meta.raw_set("__index", table)?;
+    meta.raw_set("__newindex", deny_write)?;
+    meta.raw_set("__metatable", false)?;
+
+    let proxy = lua_ctx.create_table()?;
+    proxy.set_metatable(Some(meta));
+    Ok(proxy)
+}
+
+/// Returns the seed of `math.random` for a call of the contract.
+///
+/// `source` is the transaction hash, or the last block hash for view calls.
+pub fn random_seed(source: &Hash, contract: &PublicKey) -> u64 {
+    let hash = crypto::hash(&[source.as_ref(), contract.as_ref()].concat());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&hash.as_ref()[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Removes unsafe functions from the standard library and makes `math.random` deterministic.
+pub fn sandbox(lua_ctx: Context, seed: u64) -> rlua::Result<()> {
+    let globals = lua_ctx.globals();
+    for name in FORBIDDEN_GLOBALS {
+        globals.raw_set(*name, LuaValue::Nil)?;
+    }
+
+    let string: Table = globals.raw_get("string")?;
+    string.raw_set("dump", LuaValue::Nil)?;
+
+    let state = Cell::new(seed);
+    let random = lua_ctx.create_function(move |_, (m, n): (Option<i64>, Option<i64>)| {
+        let next = splitmix64(&state);
+        let (low, high) = match (m, n) {
+            (None, _) => {
+                let float = (next >> 11) as f64 / (1u64 << 53) as f64;
+                return Ok(LuaValue::Number(float));
+            }
+            (Some(m), None) => (1, m),
+            (Some(m), Some(n)) => (m, n),
+        };
+        if low > high {
+            return Err(rlua::Error::RuntimeError(
+                "bad argument to 'random' (interval is empty)".to_string(),
+            ));
+        }
+        let span = (high as u64).wrapping_sub(low as u64).wrapping_add(1);
+        let offset = if span == 0 { next } else { next % span };
+        Ok(LuaValue::Integer(low.wrapping_add(offset as i64)))
+    })?;
+
+    let math: Table = globals.raw_get("math")?;
+    math.raw_set("random", random)?;
+    math.raw_set("randomseed", LuaValue::Nil)?;
+
+    lua_ctx.load(SANDBOX_PRELUDE).exec()
+}
+
+/// Replaces heap addresses, which Lua prints for reference values in error messages.
+pub fn mask_addresses(message: &str) -> String {
+    let mut masked = String::with_capacity(message.len());
+    let mut rest = message;
+    while let Some(pos) = rest.find("0x") {
+        let (head, tail) = rest.split_at(pos);
+        masked.push_str(head);
+        let digits = tail[2..]
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .unwrap_or(tail.len() - 2);
+        let follows_type = REFERENCE_TYPES.iter().any(|ty| {
+            masked.ends_with(&format!("{}: ", ty)) || masked.ends_with(&format!("{} ", ty))
+        });
+        if follows_type && digits > 0 {
+            masked.push('?');
+        } else {
+            masked.push_str(&tail[..2 + digits]);
+        }
+        rest = &tail[2 + digits..];
+    }
+    masked.push_str(rest);
+    masked
+}
+
+fn splitmix64(state: &Cell<u64>) -> u64 {
+    let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+    state.set(next);
+    let mut z = next;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
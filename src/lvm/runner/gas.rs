@@ -0,0 +1,149 @@
+use rlua::{Context, Function, HookTriggers, Lua, Table, Value as LuaValue};
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// Number of Lua VM instructions executed between two gas meter ticks.
+pub const GAS_STEP: u32 = 100;
+
+/// Bytes of allocated, produced or scanned data covered by one unit of gas.
+pub const BYTES_PER_GAS: u64 = 32;
+
+/// Gas charged per byte of data read from or written to the blockchain storage.
+pub const STORAGE_GAS_PER_BYTE: u64 = 1;
+
+/// Registry key of the original `collectgarbage`, which is removed from the sandbox.
+const COLLECTGARBAGE_KEY: &str = "lvm.collectgarbage";
+
+/// Registry key of a weak table, whose only entry disappears on every garbage collection.
+const SENTINEL_KEY: &str = "lvm.gc_sentinel";
+
+/// Instruction-count based gas meter.
+///
+/// Every `GAS_STEP` executed instructions cost `GAS_STEP` gas. Once the limit is exceeded
+/// every following tick raises a Lua error, so the contract cannot keep running even if
+/// it catches the error with `pcall`.
+///
+/// A single instruction, like `..`, can copy megabytes of data, so each tick also charges
+/// for the memory allocated since the previous one. The garbage collector only runs
+/// when the memory limit is reached, so allocations are seen as growth of the memory
+/// in use, and a collection is known to have freed the memory up to the limit.
+#[derive(Debug, Clone)]
+pub struct GasMeter {
+    limit: u64,
+    memory_limit: u64,
+    used: Arc<AtomicU64>,
+    memory: Arc<AtomicU64>,
+}
+
+impl GasMeter {
+    pub fn new(limit: u64, memory_limit: usize) -> Self {
+        Self {
+            limit,
+            memory_limit: memory_limit as u64,
+            used: Arc::new(AtomicU64::new(0)),
+            memory: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub fn attach(&self, lua: &Lua) -> rlua::Result<()> {
+        lua.gc_stop();
+        lua.context(|lua_ctx| {
+            let collectgarbage: Function = lua_ctx.globals().raw_get("collectgarbage")?;
+            lua_ctx.set_named_registry_value(COLLECTGARBAGE_KEY, collectgarbage)?;
+            self.track_memory(lua_ctx)
+        })?;
+
+        let meter = self.clone();
+        let triggers = HookTriggers {
+            every_nth_instruction: Some(GAS_STEP),
+            ..Default::default()
+        };
+        lua.set_hook(triggers, move |lua_ctx, _| {
+            meter.charge(u64::from(GAS_STEP))?;
+            meter.charge_memory(lua_ctx)
+        });
+        Ok(())
+    }
+
+    pub fn charge(&self, amount: u64) -> rlua::Result<()> {
+        // Saturates instead of wrapping, so that no amount can lower the counter.
+        let mut used = self.used.load(Ordering::SeqCst);
+        loop {
+            let charged = used.saturating_add(amount);
+            match self
+                .used
+                .compare_exchange(used, charged, Ordering::SeqCst, Ordering::SeqCst)
+            {
+                Ok(_) => {
+                    used = charged;
+                    break;
+                }
+                Err(actual) => used = actual,
+            }
+        }
+        if used > self.limit {
+            Err(rlua::Error::RuntimeError("out of gas".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::SeqCst).min(self.limit)
+    }
+
+    /// Returns gas left before the limit is reached.
+    pub fn remaining(&self) -> u64 {
+        self.limit - self.used()
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.used.load(Ordering::SeqCst) > self.limit
+    }
+
+    /// Charges for the memory allocated since the previous charge.
+    pub fn charge_memory(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let in_use = memory_in_use(lua_ctx)?;
+        let last = self.memory.swap(in_use, Ordering::SeqCst);
+        let sentinel: Table = lua_ctx.named_registry_value(SENTINEL_KEY)?;
+        let allocated = if let LuaValue::Nil = sentinel.raw_get::<_, LuaValue>(1)? {
+            sentinel.raw_set(1, lua_ctx.create_table()?)?;
+            // The memory was full before the collection.
+            self.memory_limit.saturating_sub(last) + in_use
+        } else {
+            // Memory in use also shrinks without a collection, e.g. when a table is rehashed.
+            in_use.saturating_sub(last)
+        };
+        self.charge(allocated / BYTES_PER_GAS)
+    }
+
+    /// Collects garbage without charging for the freed memory.
+    pub fn collect_garbage(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let collectgarbage: Function = lua_ctx.named_registry_value(COLLECTGARBAGE_KEY)?;
+        collectgarbage.call::<_, ()>("collect")?;
+        self.track_memory(lua_ctx)
+    }
+
+    /// Makes the memory in use the baseline for the following charges.
+    pub fn track_memory(&self, lua_ctx: Context) -> rlua::Result<()> {
+        let sentinel = lua_ctx.create_table()?;
+        let metatable = lua_ctx.create_table()?;
+        metatable.raw_set("__mode", "v")?;
+        sentinel.set_metatable(Some(metatable));
+        sentinel.raw_set(1, lua_ctx.create_table()?)?;
+        lua_ctx.set_named_registry_value(SENTINEL_KEY, sentinel)?;
+        self.memory.store(memory_in_use(lua_ctx)?, Ordering::SeqCst);
+        Ok(())
+    }
+}
+
+/// Returns the number of bytes allocated by the Lua state.
+fn memory_in_use(lua_ctx: Context) -> rlua::Result<u64> {
+    let collectgarbage: Function = lua_ctx.named_registry_value(COLLECTGARBAGE_KEY)?;
+    // The count is given in kilobytes, with the remainder as the fractional part.
+    let kilobytes: f64 = collectgarbage.call("count")?;
+    Ok((kilobytes * 1024.0) as u64)
+}
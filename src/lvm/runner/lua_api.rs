@@ -1,3 +1,121 @@
+use std::{error::Error as StdError, fmt};
+
+use crate::lvm::{token::TokenError, value::Value};
+
+/// Errors raised by host functions.
+///
+/// They are thrown into Lua as regular errors, so a contract can catch them with `pcall`;
+/// `tostring` of a caught error yields the message below. Uncaught errors fail the
+/// transaction with a dedicated `ExecutionError` code.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HostError {
+    MalformedKey,
+    UnknownReceiver,
+    InsufficientFunds,
+    InvalidAmount,
+    MalformedSignature,
+    MalformedHex,
+    InputTooLarge,
+    KeyTooLarge,
+    ValueTooLarge,
+    ReturnsTooLarge,
+    EventTooLarge,
+    TooManyEvents,
+    ReadOnly,
+    UnknownContract,
+    CallDepthExceeded,
+    Reentrancy,
+    /// The called contract failed, the error itself fails the whole transaction.
+    CallFailed,
+    Token(TokenError),
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            HostError::MalformedKey => "malformed public key",
+            HostError::UnknownReceiver => "unknown receiver",
+            HostError::InsufficientFunds => "insufficient funds",
+            HostError::InvalidAmount => "amount must be a non-negative integer",
+            HostError::MalformedSignature => "malformed signature",
+            HostError::MalformedHex => "malformed hex string",
+            HostError::InputTooLarge => "input is too large",
+            HostError::KeyTooLarge => "state key is too large",
+            HostError::ValueTooLarge => "state value is too large",
+            HostError::ReturnsTooLarge => "returned values are too large",
+            HostError::EventTooLarge => "event is too large",
+            HostError::TooManyEvents => "too many events",
+            HostError::ReadOnly => "state changes are not allowed in view calls",
+            HostError::UnknownContract => "unknown contract",
+            HostError::CallDepthExceeded => "call depth exceeded",
+            HostError::Reentrancy => "reentrant calls are not allowed",
+            HostError::CallFailed => "contract call failed",
+            HostError::Token(e) => return fmt::Display::fmt(e, f),
+        };
+        f.write_str(description)
+    }
+}
+
+impl StdError for HostError {}
+
+impl From<TokenError> for HostError {
+    fn from(value: TokenError) -> HostError {
+        HostError::Token(value)
+    }
+}
+
 pub trait CurrencyApi {
-    fn transfer(receiver: &String, amount: u64);
-}
\ No newline at end of file
+    fn transfer(&self, receiver: &str, amount: u64) -> Result<(), HostError>;
+
+    /// Returns the balance of the wallet, or `None` if it doesn't exist.
+    fn balance(&self, owner: &str) -> Result<Option<u64>, HostError>;
+
+    fn self_balance(&self) -> u64;
+}
+
+pub trait ContractApi {
+    fn call(&self, contract: &str, fn_name: &str, args: Vec<Value>) -> Result<Vec<Value>, HostError>;
+}
+
+pub trait EventApi {
+    fn emit(&self, name: &str, data: Value) -> Result<(), HostError>;
+}
+
+/// Token ledger operations on behalf of the calling contract.
+///
+/// The contract mints and burns only its own token, other tokens it can hold and move
+/// like any other account.
+pub trait TokenApi {
+    fn token_create(&self, name: &str, symbol: &str, decimals: u32) -> Result<(), HostError>;
+
+    fn token_mint(&self, to: &str, amount: u64) -> Result<(), HostError>;
+
+    /// Burns tokens held by the contract itself.
+    fn token_burn(&self, amount: u64) -> Result<(), HostError>;
+
+    fn token_transfer(&self, token: &str, to: &str, amount: u64) -> Result<(), HostError>;
+
+    fn token_approve(&self, token: &str, spender: &str, amount: u64) -> Result<(), HostError>;
+
+    fn token_transfer_from(
+        &self,
+        token: &str,
+        from: &str,
+        to: &str,
+        amount: u64,
+    ) -> Result<(), HostError>;
+
+    /// Returns the balance of the holder, or `None` if the token doesn't exist.
+    fn token_balance(&self, token: &str, holder: &str) -> Result<Option<u64>, HostError>;
+
+    /// Returns the allowance, or `None` if the token doesn't exist.
+    fn token_allowance(
+        &self,
+        token: &str,
+        owner: &str,
+        spender: &str,
+    ) -> Result<Option<u64>, HostError>;
+
+    /// Returns the total supply, or `None` if the token doesn't exist.
+    fn token_supply(&self, token: &str) -> Result<Option<u64>, HostError>;
+}
@@ -0,0 +1,208 @@
+use rlua::{
+    Context, Function, Integer, MultiValue, RegistryKey, String as LuaString, Table,
+    Value as LuaValue, Variadic,
+};
+
+use super::gas::{GasMeter, BYTES_PER_GAS};
+
+/// Pattern matching can backtrack exponentially within a single VM instruction,
+/// where the gas hook never fires, so only plain `string.find` is kept.
+const PATTERN_FUNCTIONS: &[&str] = &["match", "gmatch", "gsub"];
+
+/// Characters with a special meaning in Lua patterns.
+const MAGIC_CHARACTERS: &[u8] = b"^$*+?.([%-";
+
+/// Charges gas for library functions whose work is not bounded by the instruction count.
+///
+/// The gas hook only runs between VM instructions, so a single call of such a function
+/// would otherwise run unmetered however much data it processes.
+pub fn register(lua_ctx: Context, gas: &GasMeter) -> rlua::Result<()> {
+    let globals = lua_ctx.globals();
+
+    let string: Table = globals.raw_get("string")?;
+    for name in PATTERN_FUNCTIONS {
+        string.raw_set(*name, LuaValue::Nil)?;
+    }
+
+    let meter = gas.clone();
+    let rep = original(lua_ctx, &string, "rep")?;
+    let rep_fn = lua_ctx.create_function(
+        move |lua_ctx, (s, n, sep): (LuaString, Integer, Option<LuaString>)| {
+            // Each repetition costs at least a byte, as empty pieces are still copied in a loop.
+            let count = n.max(0) as u64;
+            let piece = byte_len(&s) + sep.as_ref().map_or(0, byte_len);
+            meter.charge(1 + count.saturating_mul(piece.max(1)) / BYTES_PER_GAS)?;
+            let rep: Function = lua_ctx.registry_value(&rep)?;
+            rep.call::<_, LuaString>((s, n, sep))
+        },
+    )?;
+    string.raw_set("rep", rep_fn)?;
+
+    let meter = gas.clone();
+    let find = original(lua_ctx, &string, "find")?;
+    let find_fn = lua_ctx.create_function(
+        move |lua_ctx,
+              (s, needle, init, plain): (LuaString, LuaString, Option<Integer>, LuaValue)| {
+            let plain = match plain {
+                LuaValue::Nil | LuaValue::Boolean(false) => false,
+                _ => true,
+            };
+            // A pattern would silently match something else in a plain search.
+            if !plain && is_pattern(&needle) {
+                return Err(rlua::Error::RuntimeError(
+                    "bad argument #2 to 'find' (patterns are not supported, use a plain search)"
+                        .to_string(),
+                ));
+            }
+            // Naive substring search compares the needle at every position in the worst case.
+            let work = byte_len(&s).saturating_mul(byte_len(&needle).max(1));
+            meter.charge(1 + work / BYTES_PER_GAS)?;
+            let find: Function = lua_ctx.registry_value(&find)?;
+            find.call::<_, MultiValue>((s, needle, init, true))
+        },
+    )?;
+    string.raw_set("find", find_fn)?;
+
+    let meter = gas.clone();
+    let format = original(lua_ctx, &string, "format")?;
+    let format_fn = lua_ctx.create_function(move |lua_ctx, args: Variadic<LuaValue>| {
+        let format: Function = lua_ctx.registry_value(&format)?;
+        let result: LuaString = format.call(args)?;
+        meter.charge(1 + byte_len(&result) / BYTES_PER_GAS)?;
+        Ok(result)
+    })?;
+    string.raw_set("format", format_fn)?;
+
+    // These scan a string without allocating memory, which the gas hook would charge for.
+    let utf8: Table = globals.raw_get("utf8")?;
+    charge_scan(lua_ctx, gas, &string, "unpack", 1)?;
+    for name in &["len", "codepoint", "offset"] {
+        charge_scan(lua_ctx, gas, &utf8, name, 0)?;
+    }
+
+    let table: Table = globals.raw_get("table")?;
+
+    let meter = gas.clone();
+    let concat = original(lua_ctx, &table, "concat")?;
+    let concat_fn = lua_ctx.create_function(
+        move |lua_ctx,
+              (t, sep, i, j): (Table, Option<LuaString>, Option<Integer>, Option<Integer>)| {
+            let first = i.unwrap_or(1);
+            let last = match j {
+                Some(j) => j,
+                None => t.len()?,
+            };
+            meter.charge(span(first, last)?)?;
+            let concat: Function = lua_ctx.registry_value(&concat)?;
+            let result: LuaString = concat.call((t, sep, i, j))?;
+            meter.charge(byte_len(&result) / BYTES_PER_GAS)?;
+            Ok(result)
+        },
+    )?;
+    table.raw_set("concat", concat_fn)?;
+
+    let meter = gas.clone();
+    let sort = original(lua_ctx, &table, "sort")?;
+    let sort_fn =
+        lua_ctx.create_function(move |lua_ctx, (t, comp): (Table, Option<Function>)| {
+            let n = t.raw_len().max(0) as u64;
+            let log = u64::from(64 - n.leading_zeros());
+            meter.charge(n.saturating_mul(log))?;
+            let sort: Function = lua_ctx.registry_value(&sort)?;
+            sort.call::<_, ()>((t, comp))
+        })?;
+    table.raw_set("sort", sort_fn)?;
+
+    let meter = gas.clone();
+    let move_ = original(lua_ctx, &table, "move")?;
+    let move_fn = lua_ctx.create_function(
+        move |lua_ctx, (a1, f, e, t, a2): (Table, Integer, Integer, Integer, Option<Table>)| {
+            meter.charge(span(f, e)?)?;
+            let move_: Function = lua_ctx.registry_value(&move_)?;
+            move_.call::<_, Table>((a1, f, e, t, a2))
+        },
+    )?;
+    table.raw_set("move", move_fn)?;
+
+    // Inserting or removing in the middle shifts the following elements.
+    let meter = gas.clone();
+    let insert = original(lua_ctx, &table, "insert")?;
+    let insert_fn = lua_ctx.create_function(move |lua_ctx, args: MultiValue| {
+        if args.len() == 3 {
+            let mut iter = args.iter();
+            if let (Some(LuaValue::Table(t)), Some(LuaValue::Integer(pos))) =
+                (iter.next(), iter.next())
+            {
+                meter.charge(span(*pos, t.raw_len())?)?;
+            }
+        }
+        let insert: Function = lua_ctx.registry_value(&insert)?;
+        insert.call::<_, ()>(args)
+    })?;
+    table.raw_set("insert", insert_fn)?;
+
+    let meter = gas.clone();
+    let remove = original(lua_ctx, &table, "remove")?;
+    let remove_fn =
+        lua_ctx.create_function(move |lua_ctx, (t, pos): (Table, Option<Integer>)| {
+            if let Some(pos) = pos {
+                meter.charge(span(pos, t.raw_len())?)?;
+            }
+            let remove: Function = lua_ctx.registry_value(&remove)?;
+            remove.call::<_, LuaValue>((t, pos))
+        })?;
+    table.raw_set("remove", remove_fn)?;
+
+    Ok(())
+}
+
+/// Keeps the original library function in the registry, so that the wrapper can call it.
+fn original(lua_ctx: Context, library: &Table, name: &str) -> rlua::Result<RegistryKey> {
+    let function: Function = library.raw_get(name)?;
+    lua_ctx.create_registry_value(function)
+}
+
+/// Wraps a library function to charge for the length of its string argument at `index`.
+fn charge_scan(
+    lua_ctx: Context,
+    gas: &GasMeter,
+    library: &Table,
+    name: &str,
+    index: usize,
+) -> rlua::Result<()> {
+    let meter = gas.clone();
+    let function = original(lua_ctx, library, name)?;
+    let wrapper = lua_ctx.create_function(move |lua_ctx, args: MultiValue| {
+        let scanned = match args.iter().nth(index) {
+            Some(LuaValue::String(s)) => byte_len(s),
+            _ => 0,
+        };
+        meter.charge(1 + scanned / BYTES_PER_GAS)?;
+        let function: Function = lua_ctx.registry_value(&function)?;
+        function.call::<_, MultiValue>(args)
+    })?;
+    library.raw_set(name, wrapper)
+}
+
+fn is_pattern(s: &LuaString) -> bool {
+    s.as_bytes().iter().any(|c| MAGIC_CHARACTERS.contains(c))
+}
+
+fn byte_len(s: &LuaString) -> u64 {
+    s.as_bytes().len() as u64
+}
+
+/// Returns the number of elements in the range `first..=last`.
+///
+/// Library functions reject ranges with more elements than the largest integer,
+/// so such ranges fail here, before any gas is charged for them.
+fn span(first: Integer, last: Integer) -> rlua::Result<u64> {
+    if last < first {
+        return Ok(0);
+    }
+    let count = i128::from(last) - i128::from(first) + 1;
+    if count > i128::from(Integer::max_value()) {
+        return Err(rlua::Error::RuntimeError("too many elements".to_string()));
+    }
+    Ok(count as u64)
+}
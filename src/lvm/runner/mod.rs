@@ -1,6 +1,31 @@
-pub use runner::{Runner, State};
+//! Execution of contracts in a sandboxed Lua 5.3 VM.
+//!
+//! Contracts can use the `base`, `table`, `string`, `utf8` and `math` libraries,
+//! with the following differences from stock Lua:
+//!
+//! - `dofile`, `loadfile`, `collectgarbage`, `print`, `string.dump` and `math.randomseed`
+//!   are removed, `load` accepts only text chunks, and `math.random` is seeded
+//!   from the transaction.
+//! - There is no pattern matching: `string.match`, `string.gmatch` and `string.gsub`
+//!   are removed, and `string.find` only searches for plain substrings. It raises
+//!   an error if the needle contains pattern characters, unless `plain` is `true`.
+//! - `tostring` prints reference values with deterministic ids instead of addresses,
+//!   and `next` and `pairs` iterate over keys in sorted order.
+//! - Library functions which process a lot of data within one call charge gas
+//!   for it, as does memory allocation.
+
+pub use bigint::BigInt;
+pub use context_wrap::{
+    MAX_CALL_DEPTH, MAX_EVENT_SIZE, MAX_STATE_KEY_SIZE, MAX_STATE_VALUE_SIZE, MAX_TX_EVENTS,
+};
+pub use lua_api::HostError;
+pub use runner::{validate_code, Error, Execution, Failure, Runner, MAX_RETURNS_SIZE};
 
 mod runner;
+mod bigint;
+mod crypto;
+mod env;
+mod gas;
 mod lua_api;
-#[allow(unsafe_code)]
-mod context_wrap;
\ No newline at end of file
+mod metered;
+mod context_wrap;
@@ -1,59 +1,332 @@
-use exonum::{blockchain::TransactionContext};
+use exonum::{
+    blockchain::Schema as CoreSchema,
+    crypto::{Hash, PublicKey},
+    storage::Fork,
+};
 
-use rlua::{Function, Lua, MultiValue, StdLib};
+use rlua::{FromLua, Function, Lua, MultiValue, StdLib, ToLua, Value as LuaValue};
 
-use std::collections::HashMap;
+use std::collections::BTreeSet;
 
-use crate::{currency::wallet::Wallet, lvm::contract::Contract};
+use crate::{
+    currency::wallet::Wallet,
+    lvm::{
+        abi::{AbiError, FunctionAbi},
+        contract::Contract,
+        event::Event,
+        schema::Schema as LvmSchema,
+        value::Value,
+    },
+};
 
-use super::{context_wrap::RunnerCtxWrap};
+use super::{
+    bigint,
+    context_wrap::RunnerCtxWrap,
+    crypto, env,
+    gas::{GasMeter, STORAGE_GAS_PER_BYTE},
+    lua_api::HostError,
+    metered,
+};
 
-pub type State = HashMap<String, String>;
+/// Hooks called by the service itself, neither transactions nor contracts can call them.
+const RESERVED_HOOKS: &[&str] = &["init", "migrate"];
+/// Maximum total size of the values returned by a call in their binary representation.
+pub const MAX_RETURNS_SIZE: u64 = 64 * 1024;
+
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Out of gas")]
+    OutOfGas,
+    #[fail(display = "Out of memory")]
+    OutOfMemory,
+    #[fail(display = "{}", _0)]
+    InvalidCode(String),
+    #[fail(display = "{}", _0)]
+    Abi(AbiError),
+    #[fail(display = "{}", _0)]
+    Host(HostError),
+    #[fail(display = "{}", _0)]
+    Lua(String),
+}
+
+/// Error of a failed call together with the gas it consumed.
+#[derive(Debug, Fail)]
+#[fail(display = "{} (gas used: {})", error, gas_used)]
+pub struct Failure {
+    pub error: Error,
+    pub gas_used: u64,
+}
+
+/// Compiles the contract code without running it.
+pub fn validate_code(code: &str) -> Result<(), Error> {
+    let lua = Lua::new_with(StdLib::empty());
+    lua.context(|lua_ctx| lua_ctx.load(code).into_function().map(|_| ()))
+        .map_err(|e| match e {
+            rlua::Error::SyntaxError { message, .. } => Error::InvalidCode(message),
+            e => e.into(),
+        })
+}
+
+#[derive(Debug)]
+pub struct Execution {
+    pub contract: Contract,
+    /// State entries changed by the call, `nil` values mean removal.
+    pub state_changes: Vec<(String, Value)>,
+    pub returns: Vec<Value>,
+    /// Events emitted by the call, in order of emission.
+    pub events: Vec<Event>,
+    pub gas_used: u64,
+}
 
 #[derive(Debug)]
-pub struct Runner<'a, 'ctx> {
+pub struct Runner<'a> {
     pub contract: Contract,
     pub contract_wallet: Wallet,
-    pub context: &'a mut TransactionContext<'ctx>,
+    pub fork: &'a mut Fork,
+    pub caller: PublicKey,
+    /// Hash of the executed transaction, or `None` for read-only view calls.
+    pub tx_hash: Option<Hash>,
+    /// Amount paid to the contract by the caller along with the call.
+    pub value: u64,
+    pub gas_limit: u64,
+    pub memory_limit: usize,
+    /// Contracts which called this one, empty for top-level calls.
+    pub call_stack: Vec<PublicKey>,
 }
 
-impl Runner<'_, '_> {
-    pub fn exec(mut self, fn_name: &str, args: Vec<String>) -> Result<Contract, String> {
-        let lvm_lua_subset = StdLib::BASE
-            | StdLib::TABLE
-            | StdLib::STRING
-            | StdLib::UTF8
-            | StdLib::MATH
-            | StdLib::PACKAGE;
+impl Runner<'_> {
+    pub fn exec(self, fn_name: &str, args: Vec<Value>) -> Result<Execution, Failure> {
+        self.run(fn_name, args, false)
+            .map(|execution| execution.expect("Required functions are always called"))
+    }
+
+    /// Calls an optional hook, returns `None` if the contract does not define it.
+    pub fn exec_hook(self, fn_name: &str, args: Vec<Value>) -> Result<Option<Execution>, Failure> {
+        self.run(fn_name, args, true)
+    }
+
+    fn run(
+        self,
+        fn_name: &str,
+        args: Vec<Value>,
+        optional: bool,
+    ) -> Result<Option<Execution>, Failure> {
+        let gas = GasMeter::new(self.gas_limit, self.memory_limit);
+        self.run_metered(fn_name, args, optional, &gas)
+            .map_err(|error| Failure {
+                error,
+                gas_used: gas.used(),
+            })
+    }
+
+    fn run_metered(
+        self,
+        fn_name: &str,
+        args: Vec<Value>,
+        optional: bool,
+        gas: &GasMeter,
+    ) -> Result<Option<Execution>, Error> {
+        // `env::sandbox` further removes unsafe functions of these libraries.
+        let lvm_lua_subset =
+            StdLib::BASE | StdLib::TABLE | StdLib::STRING | StdLib::UTF8 | StdLib::MATH;
         let lua = Lua::new_with(lvm_lua_subset);
+        lua.set_memory_limit(Some(self.memory_limit));
+        gas.attach(&lua)?;
+
+        let Runner {
+            contract,
+            contract_wallet,
+            fork,
+            caller,
+            tx_hash,
+            value,
+            memory_limit,
+            mut call_stack,
+            ..
+        } = self;
+        let read_only = tx_hash.is_none();
 
-        RunnerCtxWrap::init(&mut self);
+        if !optional && RESERVED_HOOKS.contains(&fn_name) {
+            return Err(Error::Abi(AbiError(format!(
+                "function `{}` cannot be called directly",
+                fn_name
+            ))));
+        }
+
+        // Hooks are checked only when declared, other functions must be declared
+        // unless the contract has no ABI.
+        let function_abi: Option<FunctionAbi> = contract.abi.function(fn_name).cloned();
+        if function_abi.is_none() && !optional && !contract.abi.is_empty() {
+            return Err(Error::Abi(AbiError(format!(
+                "function `{}` is not declared",
+                fn_name
+            ))));
+        }
+        if let Some(function_abi) = &function_abi {
+            function_abi.check_args(&args).map_err(Error::Abi)?;
+        }
+
+        let code = LvmSchema::new(&*fork)
+            .code(&contract.code_hash)
+            .ok_or_else(|| Error::Lua("contract code not found".to_string()))?;
+
+        // The transaction is executed within the block following the last committed one.
+        let (height, last_block_hash) = {
+            let schema = CoreSchema::new(&*fork);
+            let block_hashes = schema.block_hashes_by_height();
+            (block_hashes.len(), block_hashes.last().unwrap_or_else(Hash::zero))
+        };
+
+        let seed = env::random_seed(&tx_hash.unwrap_or(last_block_hash), &contract.pub_key);
+
+        call_stack.push(contract.pub_key);
+        let wrap = RunnerCtxWrap::new(
+            &lua,
+            contract_wallet,
+            fork,
+            tx_hash,
+            gas.clone(),
+            memory_limit,
+            call_stack,
+        );
 
         let result: rlua::Result<_> = lua.context(|lua_ctx| {
-            let globals = lua_ctx.globals();
+            lua_ctx.scope(|scope| {
+                let globals = lua_ctx.globals();
+
+                env::sandbox(lua_ctx, seed)?;
+                bigint::register(lua_ctx)?;
+                crypto::register(lua_ctx, gas)?;
+                metered::register(lua_ctx, gas)?;
+                wrap.register_state(&lua_ctx, scope)?;
+
+                let msg = lua_ctx.create_table()?;
+                msg.raw_set("caller", hex::encode(&caller))?;
+                msg.raw_set("tx_hash", tx_hash.map(|hash| hex::encode(&hash)))?;
+                msg.raw_set("contract", hex::encode(&contract.pub_key))?;
+                msg.raw_set("value", value)?;
+                globals.raw_set("msg", env::read_only(lua_ctx, msg)?)?;
+
+                let chain = lua_ctx.create_table()?;
+                chain.raw_set("height", height)?;
+                chain.raw_set("last_block_hash", hex::encode(&last_block_hash))?;
+                globals.raw_set("chain", env::read_only(lua_ctx, chain)?)?;
+
+                wrap.register_functions(&lua_ctx, scope)?;
+                // Memory taken by the environment is not charged for.
+                gas.track_memory(lua_ctx)?;
+
+                // Globals defined before the contract code are library and host functions,
+                // which cannot be called as entry points.
+                let mut builtins = BTreeSet::new();
+                for entry in globals.clone().pairs::<LuaValue, LuaValue>() {
+                    if let (LuaValue::String(name), _) = entry? {
+                        builtins.insert(name.to_str()?.to_owned());
+                    }
+                }
 
-            let state_table = lua_ctx.pack(self.contract.state.clone())?;
-            globals.raw_set("state", state_table)?;
+                lua_ctx.load(&code).exec()?;
 
-            RunnerCtxWrap::register_functions(&lua_ctx)?;
+                for declared in &contract.abi.functions {
+                    if builtins.contains(&declared.name) {
+                        return Err(rlua::Error::external(AbiError(format!(
+                            "declared function `{}` is a built-in function",
+                            declared.name
+                        ))));
+                    }
+                    if let LuaValue::Function(_) = globals.get(declared.name.as_str())? {
+                        continue;
+                    }
+                    return Err(rlua::Error::external(AbiError(format!(
+                        "declared function `{}` is not defined",
+                        declared.name
+                    ))));
+                }
 
-            lua_ctx.load(&self.contract.code).exec()?;
+                if builtins.contains(fn_name) {
+                    return Err(rlua::Error::external(AbiError(format!(
+                        "function `{}` is not defined by the contract",
+                        fn_name
+                    ))));
+                }
+                let func: Function = match globals.get(fn_name)? {
+                    LuaValue::Nil if optional => return Ok(None),
+                    value => Function::from_lua(value, lua_ctx)?,
+                };
+                let args: rlua::Result<Vec<_>> =
+                    args.into_iter().map(|v| v.to_lua(lua_ctx)).collect();
+                let args = MultiValue::from_vec(args?);
+                let returns: MultiValue = func.call(args)?;
+                gas.charge_memory(lua_ctx)?;
+                let returns: rlua::Result<Vec<_>> = returns
+                    .into_iter()
+                    .map(|v| Value::from_lua(v, lua_ctx))
+                    .collect();
 
-            let func: Function = globals.get(fn_name)?;
-            let args: rlua::Result<Vec<_>> = args.into_iter().map(|v| lua_ctx.pack(v)).collect();
-            let args = MultiValue::from_vec(args?);
-            func.call::<_, ()>(args)?;
+                let returns = returns?;
+                // Returned values are stored in the transaction receipt.
+                let returns_size: u64 = returns.iter().map(Value::byte_size).sum();
+                if returns_size > MAX_RETURNS_SIZE {
+                    return Err(rlua::Error::external(HostError::ReturnsTooLarge));
+                }
+                gas.charge(returns_size * STORAGE_GAS_PER_BYTE)?;
+                if let Some(function_abi) = &function_abi {
+                    function_abi
+                        .check_returns(&returns)
+                        .map_err(rlua::Error::external)?;
+                }
 
-            let state_table = globals.raw_get("state")?;
-            self.contract.state = lua_ctx.unpack(state_table)?;
-            Ok(())
+                let state_changes = wrap.state_changes(&lua_ctx)?;
+                if read_only && !state_changes.is_empty() {
+                    return Err(rlua::Error::external(HostError::ReadOnly));
+                }
+                Ok(Some((state_changes, returns, wrap.take_events())))
+            })
         });
 
-        RunnerCtxWrap::reset();
+        if gas.is_exhausted() {
+            return Err(Error::OutOfGas);
+        }
+        if let Some(e) = wrap.take_call_error() {
+            return Err(e);
+        }
 
         match result {
-            Ok(()) => Ok(self.contract.clone()),
-            Err(e) => Err(format!("{}", e)),
+            Ok(Some((state_changes, returns, events))) => Ok(Some(Execution {
+                contract,
+                state_changes,
+                returns,
+                events,
+                gas_used: gas.used(),
+            })),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e.into()),
         }
     }
 }
+
+impl From<rlua::Error> for Error {
+    fn from(err: rlua::Error) -> Self {
+        match root_cause(&err) {
+            rlua::Error::MemoryError(_) => Error::OutOfMemory,
+            rlua::Error::ExternalError(cause) => {
+                if let Some(host_error) = cause.downcast_ref::<HostError>() {
+                    Error::Host(host_error.clone())
+                } else if let Some(abi_error) = cause.downcast_ref::<AbiError>() {
+                    Error::Abi(abi_error.clone())
+                } else {
+                    Error::Lua(env::mask_addresses(&err.to_string()))
+                }
+            }
+            _ => Error::Lua(env::mask_addresses(&err.to_string())),
+        }
+    }
+}
+
+/// Unwraps errors which went through Rust callbacks.
+fn root_cause(err: &rlua::Error) -> &rlua::Error {
+    match err {
+        rlua::Error::CallbackError { cause, .. } => root_cause(cause),
+        _ => err,
+    }
+}
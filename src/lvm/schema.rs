@@ -1,9 +1,32 @@
 use exonum::{
-    crypto::{Hash, PublicKey},
-    storage::{Fork, ProofMapIndex, Snapshot},
+    crypto::{self, Hash, PublicKey},
+    storage::{Fork, ProofListIndex, ProofMapIndex, Snapshot},
 };
 
-use super::contract::Contract;
+use super::{
+    abi::Abi,
+    contract::{Contract, StateEntry},
+    event::Event,
+    receipt::Receipt,
+    token::{Token, TokenError, MAX_TOKEN_SUPPLY},
+    value::Value,
+};
+
+/// Returns the key under which `key` of the contract state is stored.
+pub fn state_key(key: &str) -> Hash {
+    crypto::hash(key.as_bytes())
+}
+
+/// Returns the address of the contract created by `author` with the given nonce.
+pub fn contract_address(author: &PublicKey, nonce: u64) -> PublicKey {
+    let hash = crypto::hash(&[author.as_ref(), &nonce.to_le_bytes()].concat());
+    PublicKey::from_slice(hash.as_ref()).expect("Hash and PublicKey have equal lengths")
+}
+
+/// Returns the key under which the allowance given by `owner` to `spender` is stored.
+pub fn allowance_key(owner: &PublicKey, spender: &PublicKey) -> Hash {
+    crypto::hash(&[owner.as_ref(), spender.as_ref()].concat())
+}
 
 #[derive(Debug)]
 pub struct Schema<T> {
@@ -25,7 +48,13 @@ where
     }
 
     pub fn state_hash(&self) -> Vec<Hash> {
-        vec![self.contracts().merkle_root()]
+        vec![
+            self.contracts().merkle_root(),
+            self.receipts().merkle_root(),
+            self.nonces().merkle_root(),
+            self.code_store().merkle_root(),
+            self.tokens().merkle_root(),
+        ]
     }
 
     pub fn contracts(&self) -> ProofMapIndex<&T, PublicKey, Contract> {
@@ -35,6 +64,99 @@ where
     pub fn contract(&self, pub_key: &PublicKey) -> Option<Contract> {
         self.contracts().get(pub_key)
     }
+
+    /// Returns contract code by its hash, shared by all contracts with the same code.
+    pub fn code_store(&self) -> ProofMapIndex<&T, Hash, String> {
+        ProofMapIndex::new("lvm.code", &self.view)
+    }
+
+    pub fn code(&self, code_hash: &Hash) -> Option<String> {
+        self.code_store().get(code_hash)
+    }
+
+    /// Returns numbers of contracts created by each author.
+    pub fn nonces(&self) -> ProofMapIndex<&T, PublicKey, u64> {
+        ProofMapIndex::new("lvm.nonces", &self.view)
+    }
+
+    /// Returns the nonce of the next contract created by `author`.
+    pub fn nonce(&self, author: &PublicKey) -> u64 {
+        self.nonces().get(author).unwrap_or(0)
+    }
+
+    /// Returns the address of the next contract created by `author`.
+    pub fn next_contract_address(&self, author: &PublicKey) -> PublicKey {
+        contract_address(author, self.nonce(author))
+    }
+
+    /// Returns state of the contract with the given public key.
+    pub fn contract_state(&self, pub_key: &PublicKey) -> ProofMapIndex<&T, Hash, StateEntry> {
+        ProofMapIndex::new_in_family("lvm.contract_state", pub_key, &self.view)
+    }
+
+    /// Returns value stored under `key` in the contract state.
+    pub fn state_value(&self, pub_key: &PublicKey, key: &str) -> Option<Value> {
+        self.contract_state(pub_key)
+            .get(&state_key(key))
+            .map(|entry| entry.value)
+    }
+
+    /// Returns events emitted by the contract with the given public key.
+    pub fn contract_events(&self, pub_key: &PublicKey) -> ProofListIndex<&T, Event> {
+        ProofListIndex::new_in_family("lvm.contract_events", pub_key, &self.view)
+    }
+
+    /// Returns events emitted by the transaction with the given hash.
+    pub fn tx_events(&self, tx_hash: &Hash) -> ProofListIndex<&T, Event> {
+        ProofListIndex::new_in_family("lvm.tx_events", tx_hash, &self.view)
+    }
+
+    pub fn receipts(&self) -> ProofMapIndex<&T, Hash, Receipt> {
+        ProofMapIndex::new("lvm.receipts", &self.view)
+    }
+
+    pub fn receipt(&self, tx_hash: &Hash) -> Option<Receipt> {
+        self.receipts().get(tx_hash)
+    }
+
+    /// Returns tokens by the keys of their issuing contracts.
+    pub fn tokens(&self) -> ProofMapIndex<&T, PublicKey, Token> {
+        ProofMapIndex::new("lvm.tokens", &self.view)
+    }
+
+    pub fn token(&self, pub_key: &PublicKey) -> Option<Token> {
+        self.tokens().get(pub_key)
+    }
+
+    /// Returns non-zero balances of the token holders.
+    pub fn token_balances(&self, token: &PublicKey) -> ProofMapIndex<&T, PublicKey, u64> {
+        ProofMapIndex::new_in_family("lvm.token_balances", token, &self.view)
+    }
+
+    pub fn token_balance(&self, token: &PublicKey, holder: &PublicKey) -> u64 {
+        self.token_balances(token).get(holder).unwrap_or(0)
+    }
+
+    /// Returns non-zero allowances of the token, keyed by `allowance_key`.
+    pub fn token_allowances(&self, token: &PublicKey) -> ProofMapIndex<&T, Hash, u64> {
+        ProofMapIndex::new_in_family("lvm.token_allowances", token, &self.view)
+    }
+
+    pub fn token_allowance(
+        &self,
+        token: &PublicKey,
+        owner: &PublicKey,
+        spender: &PublicKey,
+    ) -> u64 {
+        self.token_allowances(token)
+            .get(&allowance_key(owner, spender))
+            .unwrap_or(0)
+    }
+
+    /// Returns history of the token with the given public key.
+    pub fn token_history(&self, token: &PublicKey) -> ProofListIndex<&T, Hash> {
+        ProofListIndex::new_in_family("lvm.token_history", token, &self.view)
+    }
 }
 
 impl Schema<&mut Fork> {
@@ -42,8 +164,300 @@ impl Schema<&mut Fork> {
         ProofMapIndex::new("lvm.contracts", &mut self.view)
     }
 
-    pub fn create_contract(&mut self, pub_key: &PublicKey, code: &str) {
-        let contract = Contract::new(pub_key, code);
-        self.contracts_mut().put(pub_key, contract);
+    pub fn contract_state_mut(
+        &mut self,
+        pub_key: &PublicKey,
+    ) -> ProofMapIndex<&mut Fork, Hash, StateEntry> {
+        ProofMapIndex::new_in_family("lvm.contract_state", pub_key, &mut self.view)
+    }
+
+    pub fn contract_events_mut(&mut self, pub_key: &PublicKey) -> ProofListIndex<&mut Fork, Event> {
+        ProofListIndex::new_in_family("lvm.contract_events", pub_key, &mut self.view)
+    }
+
+    pub fn tx_events_mut(&mut self, tx_hash: &Hash) -> ProofListIndex<&mut Fork, Event> {
+        ProofListIndex::new_in_family("lvm.tx_events", tx_hash, &mut self.view)
+    }
+
+    pub fn receipts_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, Receipt> {
+        ProofMapIndex::new("lvm.receipts", &mut self.view)
+    }
+
+    pub fn code_store_mut(&mut self) -> ProofMapIndex<&mut Fork, Hash, String> {
+        ProofMapIndex::new("lvm.code", &mut self.view)
+    }
+
+    /// Adds code to the code store unless it is already there, returns its hash.
+    pub fn put_code(&mut self, code: &str) -> Hash {
+        let code_hash = crypto::hash(code.as_bytes());
+        if !self.code_store().contains(&code_hash) {
+            self.code_store_mut().put(&code_hash, code.to_string());
+        }
+        code_hash
+    }
+
+    pub fn nonces_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, u64> {
+        ProofMapIndex::new("lvm.nonces", &mut self.view)
+    }
+
+    /// Increments the nonce of `author` after creating a contract.
+    pub fn increment_nonce(&mut self, author: &PublicKey) {
+        let nonce = self.nonce(author);
+        self.nonces_mut().put(author, nonce + 1);
+    }
+
+    pub fn create_contract(
+        &mut self,
+        pub_key: &PublicKey,
+        owner: &PublicKey,
+        code: &str,
+        abi: Abi,
+    ) -> Contract {
+        let code_hash = self.put_code(code);
+        let state_hash = self.contract_state(pub_key).merkle_root();
+        let events_hash = self.contract_events(pub_key).merkle_root();
+        let contract = Contract::new(
+            pub_key,
+            owner,
+            &code_hash,
+            &state_hash,
+            0,
+            &events_hash,
+            abi,
+        );
+        self.contracts_mut().put(pub_key, contract.clone());
+        contract
+    }
+
+    /// Writes changed keys into the contract state and updates its state hash.
+    ///
+    /// Keys set to `nil` are removed from the state.
+    pub fn update_contract_state(
+        &mut self,
+        contract: Contract,
+        changes: Vec<(String, Value)>,
+    ) -> Contract {
+        let contract = {
+            let mut state = self.contract_state_mut(&contract.pub_key);
+            for (key, value) in changes {
+                match value {
+                    Value::Nil => state.remove(&state_key(&key)),
+                    value => state.put(&state_key(&key), StateEntry::new(&key, value)),
+                }
+            }
+            let state_hash = state.merkle_root();
+            contract.set_state_hash(&state_hash)
+        };
+        self.contracts_mut().put(&contract.pub_key, contract.clone());
+        contract
+    }
+
+    /// Appends events emitted by the contract within the given transaction.
+    pub fn append_contract_events(
+        &mut self,
+        contract: Contract,
+        tx_hash: &Hash,
+        events: Vec<Event>,
+    ) -> Contract {
+        if events.is_empty() {
+            return contract;
+        }
+
+        self.tx_events_mut(tx_hash).extend(events.iter().cloned());
+        let contract = {
+            let mut history = self.contract_events_mut(&contract.pub_key);
+            history.extend(events);
+            let events_hash = history.merkle_root();
+            contract.set_events(history.len(), &events_hash)
+        };
+        self.contracts_mut().put(&contract.pub_key, contract.clone());
+        contract
+    }
+
+    pub fn tokens_mut(&mut self) -> ProofMapIndex<&mut Fork, PublicKey, Token> {
+        ProofMapIndex::new("lvm.tokens", &mut self.view)
+    }
+
+    pub fn token_balances_mut(
+        &mut self,
+        token: &PublicKey,
+    ) -> ProofMapIndex<&mut Fork, PublicKey, u64> {
+        ProofMapIndex::new_in_family("lvm.token_balances", token, &mut self.view)
+    }
+
+    pub fn token_allowances_mut(
+        &mut self,
+        token: &PublicKey,
+    ) -> ProofMapIndex<&mut Fork, Hash, u64> {
+        ProofMapIndex::new_in_family("lvm.token_allowances", token, &mut self.view)
+    }
+
+    pub fn token_history_mut(&mut self, token: &PublicKey) -> ProofListIndex<&mut Fork, Hash> {
+        ProofListIndex::new_in_family("lvm.token_history", token, &mut self.view)
+    }
+
+    /// Issues a token of the contract with the given public key.
+    pub fn create_token(
+        &mut self,
+        pub_key: &PublicKey,
+        name: &str,
+        symbol: &str,
+        decimals: u32,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        if self.tokens().contains(pub_key) {
+            return Err(TokenError::TokenExists);
+        }
+        let token = Token::new(pub_key, name, symbol, decimals);
+        Ok(self.commit_token(token, transaction))
+    }
+
+    /// Creates `amount` of new tokens on the balance of `to`.
+    pub fn mint_tokens(
+        &mut self,
+        token: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        let token = self.token(token).ok_or(TokenError::UnknownToken)?;
+        let total_supply = token
+            .total_supply
+            .checked_add(amount)
+            .filter(|&supply| supply <= MAX_TOKEN_SUPPLY)
+            .ok_or(TokenError::SupplyOverflow)?;
+        // A single balance never exceeds the total supply, so it cannot overflow either.
+        let balance = self.token_balance(&token.pub_key, to);
+        self.set_token_balance(&token.pub_key, to, balance + amount);
+        Ok(self.commit_token(token.set_total_supply(total_supply), transaction))
+    }
+
+    /// Destroys `amount` of tokens from the balance of `from`.
+    pub fn burn_tokens(
+        &mut self,
+        token: &PublicKey,
+        from: &PublicKey,
+        amount: u64,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        let token = self.token(token).ok_or(TokenError::UnknownToken)?;
+        let balance = self.token_balance(&token.pub_key, from);
+        if balance < amount {
+            return Err(TokenError::InsufficientTokens);
+        }
+        self.set_token_balance(&token.pub_key, from, balance - amount);
+        let total_supply = token.total_supply - amount;
+        Ok(self.commit_token(token.set_total_supply(total_supply), transaction))
+    }
+
+    pub fn transfer_tokens(
+        &mut self,
+        token: &PublicKey,
+        from: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        let token = self.token(token).ok_or(TokenError::UnknownToken)?;
+        self.move_tokens(&token.pub_key, from, to, amount)?;
+        Ok(self.commit_token(token, transaction))
+    }
+
+    /// Sets the amount `spender` may move from the balance of `owner`.
+    ///
+    /// Allowances above the maximum supply are capped, as no balance can exceed it.
+    pub fn approve_tokens(
+        &mut self,
+        token: &PublicKey,
+        owner: &PublicKey,
+        spender: &PublicKey,
+        amount: u64,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        let token = self.token(token).ok_or(TokenError::UnknownToken)?;
+        let amount = amount.min(MAX_TOKEN_SUPPLY);
+        self.set_token_allowance(&token.pub_key, &allowance_key(owner, spender), amount);
+        Ok(self.commit_token(token, transaction))
+    }
+
+    /// Moves tokens of `from` on behalf of `spender`, decreasing its allowance.
+    pub fn transfer_tokens_from(
+        &mut self,
+        token: &PublicKey,
+        spender: &PublicKey,
+        from: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        transaction: &Hash,
+    ) -> Result<Token, TokenError> {
+        let token = self.token(token).ok_or(TokenError::UnknownToken)?;
+        let allowance = self.token_allowance(&token.pub_key, from, spender);
+        if allowance < amount {
+            return Err(TokenError::InsufficientAllowance);
+        }
+        self.move_tokens(&token.pub_key, from, to, amount)?;
+        self.set_token_allowance(
+            &token.pub_key,
+            &allowance_key(from, spender),
+            allowance - amount,
+        );
+        Ok(self.commit_token(token, transaction))
+    }
+
+    /// Checks the balance of `from` before changing anything, so failed moves leave no trace.
+    fn move_tokens(
+        &mut self,
+        token: &PublicKey,
+        from: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+    ) -> Result<(), TokenError> {
+        let balance = self.token_balance(token, from);
+        if balance < amount {
+            return Err(TokenError::InsufficientTokens);
+        }
+        self.set_token_balance(token, from, balance - amount);
+        // Read the receiver after the withdrawal, so that self-transfers stay consistent.
+        let balance = self.token_balance(token, to);
+        self.set_token_balance(token, to, balance + amount);
+        Ok(())
+    }
+
+    /// Zero balances are removed, so that the index lists only actual holders.
+    fn set_token_balance(&mut self, token: &PublicKey, holder: &PublicKey, balance: u64) {
+        let mut balances = self.token_balances_mut(token);
+        if balance == 0 {
+            balances.remove(holder);
+        } else {
+            balances.put(holder, balance);
+        }
+    }
+
+    fn set_token_allowance(&mut self, token: &PublicKey, key: &Hash, amount: u64) {
+        let mut allowances = self.token_allowances_mut(token);
+        if amount == 0 {
+            allowances.remove(key);
+        } else {
+            allowances.put(key, amount);
+        }
+    }
+
+    /// Updates the ledger hashes of the token and appends the transaction to its history.
+    fn commit_token(&mut self, token: Token, transaction: &Hash) -> Token {
+        let balances_hash = self.token_balances(&token.pub_key).merkle_root();
+        let allowances_hash = self.token_allowances(&token.pub_key).merkle_root();
+        let token = {
+            let mut history = self.token_history_mut(&token.pub_key);
+            history.push(*transaction);
+            let history_hash = history.merkle_root();
+            token.set_ledger(
+                &balances_hash,
+                &allowances_hash,
+                history.len(),
+                &history_hash,
+            )
+        };
+        self.tokens_mut().put(&token.pub_key, token.clone());
+        token
     }
 }
@@ -4,12 +4,13 @@ use exonum::{
     crypto::Hash,
     helpers::fabric::{self, Context},
     messages::RawTransaction,
-    storage::Snapshot,
+    storage::{Fork, Snapshot},
 };
 
 use super::{
     Schema,
     api::PublicApi,
+    config::LvmConfig,
     transactions::LvmTransactions,
 };
 
@@ -33,6 +34,10 @@ impl blockchain::Service for Service {
         schema.state_hash()
     }
 
+    fn initialize(&self, _fork: &mut Fork) -> serde_json::Value {
+        serde_json::to_value(LvmConfig::default()).unwrap()
+    }
+
     fn tx_from_raw(&self, raw: RawTransaction) -> Result<Box<dyn Transaction>, failure::Error> {
         LvmTransactions::tx_from_raw(raw).map(Into::into)
     }
@@ -0,0 +1,100 @@
+use exonum::crypto::{Hash, PublicKey};
+
+use std::{error::Error as StdError, fmt};
+
+use super::proto;
+
+/// Highest total supply of a token. Amounts stay within the Lua integer range,
+/// so that contracts never see them turn into floats.
+pub const MAX_TOKEN_SUPPLY: u64 = i64::max_value() as u64;
+
+/// Fungible token issued by a contract.
+///
+/// Each contract may issue a single token, which shares the contract key. Only the issuing
+/// contract can mint and burn it, holders move it with transactions or through contracts.
+#[derive(Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::Token", serde_pb_convert)]
+pub struct Token {
+    /// `PublicKey` of the issuing contract.
+    pub pub_key: PublicKey,
+    pub name: String,
+    pub symbol: String,
+    /// Number of decimal places used to display amounts, amounts themselves are integers.
+    pub decimals: u32,
+    pub total_supply: u64,
+    /// `Hash` of the holder balances.
+    pub balances_hash: Hash,
+    /// `Hash` of the allowances given by holders.
+    pub allowances_hash: Hash,
+    /// Length of the transactions history.
+    pub history_len: u64,
+    /// `Hash` of the transactions history.
+    pub history_hash: Hash,
+}
+
+impl Token {
+    /// Creates a token without supply, its ledger hashes are set once it is stored.
+    pub fn new(pub_key: &PublicKey, name: &str, symbol: &str, decimals: u32) -> Self {
+        Self {
+            pub_key: *pub_key,
+            name: name.to_string(),
+            symbol: symbol.to_string(),
+            decimals,
+            total_supply: 0,
+            balances_hash: Hash::zero(),
+            allowances_hash: Hash::zero(),
+            history_len: 0,
+            history_hash: Hash::zero(),
+        }
+    }
+
+    /// Returns a copy of this token with updated total supply.
+    pub fn set_total_supply(self, total_supply: u64) -> Self {
+        Self {
+            total_supply,
+            ..self
+        }
+    }
+
+    /// Returns a copy of this token with updated balances, allowances and history.
+    pub fn set_ledger(
+        self,
+        balances_hash: &Hash,
+        allowances_hash: &Hash,
+        history_len: u64,
+        history_hash: &Hash,
+    ) -> Self {
+        Self {
+            balances_hash: *balances_hash,
+            allowances_hash: *allowances_hash,
+            history_len,
+            history_hash: *history_hash,
+            ..self
+        }
+    }
+}
+
+/// Errors of token ledger operations.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenError {
+    UnknownToken,
+    TokenExists,
+    InsufficientTokens,
+    InsufficientAllowance,
+    SupplyOverflow,
+}
+
+impl fmt::Display for TokenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let description = match self {
+            TokenError::UnknownToken => "unknown token",
+            TokenError::TokenExists => "token already exists",
+            TokenError::InsufficientTokens => "insufficient tokens",
+            TokenError::InsufficientAllowance => "insufficient allowance",
+            TokenError::SupplyOverflow => "token supply overflow",
+        };
+        f.write_str(description)
+    }
+}
+
+impl StdError for TokenError {}
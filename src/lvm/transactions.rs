@@ -1,12 +1,22 @@
 use exonum::{
     blockchain::{ExecutionError, ExecutionResult, Transaction, TransactionContext},
-    crypto::{PublicKey, SecretKey},
+    crypto::{Hash, PublicKey, SecretKey},
     messages::{Message, RawTransaction, Signed},
+    storage::Fork,
 };
 
 use crate::currency::schema::Schema as CurrencySchema;
 
-use crate::lvm::{proto, runner::Runner, schema::Schema as LvmSchema, service::LVM_SERVICE_ID};
+use crate::lvm::{
+    abi::{Abi, AbiError},
+    config::LvmConfig,
+    proto,
+    receipt::Receipt,
+    runner::{self, Error as RunnerError, Execution, Failure, HostError, Runner},
+    schema::Schema as LvmSchema,
+    service::LVM_SERVICE_ID,
+    token::TokenError,
+};
 
 #[derive(Debug, Fail)]
 #[repr(u8)]
@@ -17,6 +27,38 @@ pub enum Error {
     ContractNotExists = 1,
     #[fail(display = "Contract execution error")]
     ContractExecutionError = 2,
+    #[fail(display = "Out of gas")]
+    OutOfGas = 3,
+    #[fail(display = "Out of memory")]
+    OutOfMemory = 4,
+    #[fail(display = "Malformed public key")]
+    MalformedKey = 5,
+    #[fail(display = "Receiver doesn't exist")]
+    ReceiverNotFound = 6,
+    #[fail(display = "Insufficient currency amount")]
+    InsufficientCurrencyAmount = 7,
+    #[fail(display = "Not the contract owner")]
+    NotOwner = 8,
+    #[fail(display = "Invalid contract code")]
+    InvalidCode = 9,
+    #[fail(display = "Call doesn't match the contract ABI")]
+    AbiMismatch = 10,
+    #[fail(display = "Sender doesn't exist")]
+    SenderNotFound = 11,
+    #[fail(display = "Invalid currency amount")]
+    InvalidAmount = 12,
+    #[fail(display = "Token doesn't exist")]
+    TokenNotExists = 13,
+    #[fail(display = "Token already exists")]
+    TokenAlreadyExists = 14,
+    #[fail(display = "Insufficient token amount")]
+    InsufficientTokenAmount = 15,
+    #[fail(display = "Insufficient token allowance")]
+    InsufficientAllowance = 16,
+    #[fail(display = "Token supply overflow")]
+    TokenSupplyOverflow = 17,
+    #[fail(display = "Gas limit exceeds the maximum")]
+    GasLimitTooHigh = 18,
 }
 
 impl From<Error> for ExecutionError {
@@ -26,11 +68,100 @@ impl From<Error> for ExecutionError {
     }
 }
 
+impl From<RunnerError> for ExecutionError {
+    fn from(value: RunnerError) -> ExecutionError {
+        let (code, description) = describe(value);
+        ExecutionError::with_description(code, description)
+    }
+}
+
+/// Reports the gas consumed by a failed call along with the error.
+impl From<Failure> for ExecutionError {
+    fn from(value: Failure) -> ExecutionError {
+        let (code, description) = describe(value.error);
+        let description = format!("{} (gas used: {})", description, value.gas_used);
+        ExecutionError::with_description(code, description)
+    }
+}
+
+/// Returns the error code and description of a contract execution error.
+fn describe(error: RunnerError) -> (u8, String) {
+    let error = match error {
+        RunnerError::OutOfGas => Error::OutOfGas,
+        RunnerError::OutOfMemory => Error::OutOfMemory,
+        RunnerError::Host(HostError::MalformedKey) => Error::MalformedKey,
+        RunnerError::Host(HostError::UnknownReceiver) => Error::ReceiverNotFound,
+        RunnerError::Host(HostError::UnknownContract) => Error::ContractNotExists,
+        RunnerError::Host(HostError::InvalidAmount) => Error::InvalidAmount,
+        RunnerError::Host(HostError::InsufficientFunds) => Error::InsufficientCurrencyAmount,
+        RunnerError::Host(HostError::Token(e)) => e.into(),
+        RunnerError::Host(other) => {
+            return (Error::ContractExecutionError as u8, other.to_string())
+        }
+        RunnerError::Abi(e) => return (Error::AbiMismatch as u8, e.to_string()),
+        RunnerError::InvalidCode(desc) => return (Error::InvalidCode as u8, desc),
+        RunnerError::Lua(desc) => return (Error::ContractExecutionError as u8, desc),
+    };
+    let description = error.to_string();
+    (error as u8, description)
+}
+
+impl From<AbiError> for ExecutionError {
+    fn from(value: AbiError) -> ExecutionError {
+        ExecutionError::with_description(Error::AbiMismatch as u8, value.to_string())
+    }
+}
+
+impl From<TokenError> for Error {
+    fn from(value: TokenError) -> Error {
+        match value {
+            TokenError::UnknownToken => Error::TokenNotExists,
+            TokenError::TokenExists => Error::TokenAlreadyExists,
+            TokenError::InsufficientTokens => Error::InsufficientTokenAmount,
+            TokenError::InsufficientAllowance => Error::InsufficientAllowance,
+            TokenError::SupplyOverflow => Error::TokenSupplyOverflow,
+        }
+    }
+}
+
+impl From<TokenError> for ExecutionError {
+    fn from(value: TokenError) -> ExecutionError {
+        Error::from(value).into()
+    }
+}
+
+/// Deploys a contract.
+///
+/// If the code defines `init`, it is called with `args` in the same transaction,
+/// so the deployment fails together with the constructor. `init` cannot be called
+/// afterwards, and `args` are rejected if the code does not define it.
 #[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
 #[exonum(pb = "proto::CreateContract")]
 pub struct CreateContract {
+    pub code: String,
+    pub args: Vec<String>,
+    pub gas_limit: u64,
+    pub abi: Abi,
+}
+
+/// Replaces the contract code, keeping its state and wallet.
+///
+/// If the new code defines `migrate`, it is called without arguments after the replacement.
+/// Only upgrades can call `migrate`.
+#[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::UpgradeContract")]
+pub struct UpgradeContract {
     pub pub_key: PublicKey,
     pub code: String,
+    pub gas_limit: u64,
+    pub abi: Abi,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::TransferOwnership")]
+pub struct TransferOwnership {
+    pub pub_key: PublicKey,
+    pub new_owner: PublicKey,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
@@ -39,19 +170,74 @@ pub struct CallContract {
     pub pub_key: PublicKey,
     pub fn_name: String,
     pub args: Vec<String>,
+    pub gas_limit: u64,
+    /// Amount moved from the author to the contract wallet before the call.
+    ///
+    /// The payment is reverted together with the rest of the transaction if the call fails.
+    pub value: u64,
+}
+
+/// Moves tokens from the author to another account.
+#[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::TransferTokens")]
+pub struct TransferTokens {
+    pub token: PublicKey,
+    pub to: PublicKey,
+    pub amount: u64,
+}
+
+/// Allows `spender` to move up to `amount` tokens of the author.
+///
+/// The new allowance replaces the previous one, zero revokes it.
+#[derive(Serialize, Deserialize, Clone, Debug, ProtobufConvert)]
+#[exonum(pb = "proto::ApproveTokens")]
+pub struct ApproveTokens {
+    pub token: PublicKey,
+    pub spender: PublicKey,
+    pub amount: u64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, TransactionSet)]
 pub enum LvmTransactions {
     CreateContract(CreateContract),
     CallContract(CallContract),
+    UpgradeContract(UpgradeContract),
+    TransferOwnership(TransferOwnership),
+    TransferTokens(TransferTokens),
+    ApproveTokens(ApproveTokens),
 }
 
 impl CreateContract {
+    #[doc(hidden)]
+    pub fn sign(
+        code: &str,
+        args: &Vec<String>,
+        gas_limit: u64,
+        abi: &Abi,
+        pk: &PublicKey,
+        sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        Message::sign_transaction(
+            Self {
+                code: code.to_string(),
+                args: args.clone(),
+                gas_limit,
+                abi: abi.clone(),
+            },
+            LVM_SERVICE_ID,
+            *pk,
+            sk,
+        )
+    }
+}
+
+impl UpgradeContract {
     #[doc(hidden)]
     pub fn sign(
         pub_key: &PublicKey,
         code: &str,
+        gas_limit: u64,
+        abi: &Abi,
         pk: &PublicKey,
         sk: &SecretKey,
     ) -> Signed<RawTransaction> {
@@ -59,6 +245,28 @@ impl CreateContract {
             Self {
                 pub_key: *pub_key,
                 code: code.to_string(),
+                gas_limit,
+                abi: abi.clone(),
+            },
+            LVM_SERVICE_ID,
+            *pk,
+            sk,
+        )
+    }
+}
+
+impl TransferOwnership {
+    #[doc(hidden)]
+    pub fn sign(
+        pub_key: &PublicKey,
+        new_owner: &PublicKey,
+        pk: &PublicKey,
+        sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        Message::sign_transaction(
+            Self {
+                pub_key: *pub_key,
+                new_owner: *new_owner,
             },
             LVM_SERVICE_ID,
             *pk,
@@ -73,6 +281,8 @@ impl CallContract {
         pub_key: &PublicKey,
         fn_name: &str,
         args: &Vec<String>,
+        gas_limit: u64,
+        value: u64,
         pk: &PublicKey,
         sk: &SecretKey,
     ) -> Signed<RawTransaction> {
@@ -81,6 +291,52 @@ impl CallContract {
                 pub_key: *pub_key,
                 fn_name: fn_name.to_string(),
                 args: args.clone(),
+                gas_limit,
+                value,
+            },
+            LVM_SERVICE_ID,
+            *pk,
+            sk,
+        )
+    }
+}
+
+impl TransferTokens {
+    #[doc(hidden)]
+    pub fn sign(
+        token: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        pk: &PublicKey,
+        sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        Message::sign_transaction(
+            Self {
+                token: *token,
+                to: *to,
+                amount,
+            },
+            LVM_SERVICE_ID,
+            *pk,
+            sk,
+        )
+    }
+}
+
+impl ApproveTokens {
+    #[doc(hidden)]
+    pub fn sign(
+        token: &PublicKey,
+        spender: &PublicKey,
+        amount: u64,
+        pk: &PublicKey,
+        sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        Message::sign_transaction(
+            Self {
+                token: *token,
+                spender: *spender,
+                amount,
             },
             LVM_SERVICE_ID,
             *pk,
@@ -91,33 +347,65 @@ impl CallContract {
 
 impl Transaction for CreateContract {
     fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
-        {
+        let config = LvmConfig::actual(context.fork());
+        check_gas_limit(self.gas_limit, &config)?;
+        runner::validate_code(&self.code)?;
+        self.abi.validate()?;
+
+        let author = context.author();
+        let hash = context.tx_hash();
+        let contract = {
             let mut schema = LvmSchema::new(context.fork());
-            match schema.contract(&self.pub_key) {
+            let pub_key = schema.next_contract_address(&author);
+            match schema.contract(&pub_key) {
                 None => {
-                    schema.create_contract(&self.pub_key, &self.code);
+                    schema.increment_nonce(&author);
+                    schema.create_contract(&pub_key, &author, &self.code, self.abi.clone())
                 }
                 Some(_) => Err(Error::ContractAlreadyExists)?,
             }
-        }
+        };
 
-        {
-            let hash = context.tx_hash();
+        let contract_wallet = {
             let mut schema = CurrencySchema::new(context.fork());
-            if schema.wallet(&self.pub_key).is_none() {
-                let name = format!("contract-{}", &self.pub_key);
-                schema.create_wallet(&self.pub_key, &name, &hash);
-            } else {
+            if schema.wallet(&contract.pub_key).is_some() {
                 Err(Error::ContractAlreadyExists)?
             }
-        }
+            let name = format!("contract-{}", &contract.pub_key);
+            schema.create_wallet(&contract.pub_key, &name, &hash);
+            schema.wallet(&contract.pub_key).unwrap()
+        };
 
+        let args = contract.abi.parse_args("init", &self.args)?;
+        let runner = Runner {
+            contract,
+            contract_wallet,
+            fork: context.fork(),
+            caller: author,
+            tx_hash: Some(hash),
+            value: 0,
+            gas_limit: self.gas_limit,
+            memory_limit: config.memory_limit as usize,
+            call_stack: Vec::new(),
+        };
+
+        let has_args = !args.is_empty();
+        match runner.exec_hook("init", args)? {
+            Some(execution) => commit_execution(context.fork(), &hash, execution),
+            None if has_args => Err(AbiError(
+                "arguments are given, but `init` is not defined".to_string(),
+            ))?,
+            None => {}
+        }
         Ok(())
     }
 }
 
 impl Transaction for CallContract {
     fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
+        let config = LvmConfig::actual(context.fork());
+        check_gas_limit(self.gas_limit, &config)?;
+
         let contract = {
             let schema = LvmSchema::new(context.fork());
             match schema.contract(&self.pub_key) {
@@ -126,6 +414,73 @@ impl Transaction for CallContract {
             }
         };
 
+        let caller = context.author();
+        let hash = context.tx_hash();
+
+        let contract_wallet = {
+            let mut schema = CurrencySchema::new(context.fork());
+            if schema.wallet(&self.pub_key).is_none() {
+                Err(Error::ContractNotExists)?
+            }
+            if self.value > 0 {
+                let sender = match schema.wallet(&caller) {
+                    Some(w) => w,
+                    None => Err(Error::SenderNotFound)?,
+                };
+                if sender.balance < self.value {
+                    Err(Error::InsufficientCurrencyAmount)?
+                }
+                schema.decrease_wallet_balance(sender, self.value, &hash);
+                let contract_wallet = schema.wallet(&self.pub_key).unwrap();
+                schema.increase_wallet_balance(contract_wallet, self.value, &hash);
+            }
+            schema.wallet(&self.pub_key).unwrap()
+        };
+
+        let args = contract.abi.parse_args(&self.fn_name, &self.args)?;
+
+        let runner = Runner {
+            contract,
+            contract_wallet,
+            fork: context.fork(),
+            caller,
+            tx_hash: Some(hash),
+            value: self.value,
+            gas_limit: self.gas_limit,
+            memory_limit: config.memory_limit as usize,
+            call_stack: Vec::new(),
+        };
+
+        let execution = runner.exec(&self.fn_name, args)?;
+
+        commit_execution(context.fork(), &hash, execution);
+        Ok(())
+    }
+}
+
+impl Transaction for UpgradeContract {
+    fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
+        let config = LvmConfig::actual(context.fork());
+        check_gas_limit(self.gas_limit, &config)?;
+        runner::validate_code(&self.code)?;
+        self.abi.validate()?;
+
+        let author = context.author();
+        let contract = {
+            let mut schema = LvmSchema::new(context.fork());
+            let contract = match schema.contract(&self.pub_key) {
+                Some(c) => c,
+                None => Err(Error::ContractNotExists)?,
+            };
+            if contract.owner != author {
+                Err(Error::NotOwner)?
+            }
+            let code_hash = schema.put_code(&self.code);
+            let contract = contract.set_code(&code_hash, self.abi.clone());
+            schema.contracts_mut().put(&self.pub_key, contract.clone());
+            contract
+        };
+
         let contract_wallet = {
             let schema = CurrencySchema::new(context.fork());
             match schema.wallet(&self.pub_key) {
@@ -134,22 +489,82 @@ impl Transaction for CallContract {
             }
         };
 
+        let hash = context.tx_hash();
+
         let runner = Runner {
             contract,
             contract_wallet,
-            context: &mut context,
+            fork: context.fork(),
+            caller: author,
+            tx_hash: Some(hash),
+            value: 0,
+            gas_limit: self.gas_limit,
+            memory_limit: config.memory_limit as usize,
+            call_stack: Vec::new(),
         };
 
-        match runner.exec(&self.fn_name, self.args.clone()) {
-            Ok(contract) => {
-                let mut schema = LvmSchema::new(context.fork());
-                schema.contracts_mut().put(&self.pub_key, contract);
-                Ok(())
-            }
-            Err(desc) => Err(ExecutionError::with_description(
-                Error::ContractExecutionError as u8,
-                desc,
-            )),
+        if let Some(execution) = runner.exec_hook("migrate", Vec::new())? {
+            commit_execution(context.fork(), &hash, execution);
         }
+        Ok(())
     }
 }
+
+impl Transaction for TransferOwnership {
+    fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
+        let author = context.author();
+        let mut schema = LvmSchema::new(context.fork());
+        let contract = match schema.contract(&self.pub_key) {
+            Some(c) => c,
+            None => Err(Error::ContractNotExists)?,
+        };
+        if contract.owner != author {
+            Err(Error::NotOwner)?
+        }
+        schema
+            .contracts_mut()
+            .put(&self.pub_key, contract.set_owner(&self.new_owner));
+        Ok(())
+    }
+}
+
+impl Transaction for TransferTokens {
+    fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
+        let author = context.author();
+        let hash = context.tx_hash();
+        let mut schema = LvmSchema::new(context.fork());
+        schema.transfer_tokens(&self.token, &author, &self.to, self.amount, &hash)?;
+        Ok(())
+    }
+}
+
+impl Transaction for ApproveTokens {
+    fn execute(&self, mut context: TransactionContext) -> ExecutionResult {
+        let author = context.author();
+        let hash = context.tx_hash();
+        let mut schema = LvmSchema::new(context.fork());
+        schema.approve_tokens(&self.token, &author, &self.spender, self.amount, &hash)?;
+        Ok(())
+    }
+}
+
+fn check_gas_limit(gas_limit: u64, config: &LvmConfig) -> Result<(), Error> {
+    if gas_limit > config.max_gas {
+        Err(Error::GasLimitTooHigh)
+    } else {
+        Ok(())
+    }
+}
+
+/// Persists results of a successful call and records its receipt.
+fn commit_execution(fork: &mut Fork, tx_hash: &Hash, execution: Execution) {
+    let mut schema = LvmSchema::new(fork);
+    let contract = schema.update_contract_state(execution.contract, execution.state_changes);
+    schema.append_contract_events(contract, tx_hash, execution.events);
+    // Events of nested calls are already in the list, so the root covers the whole transaction.
+    let events_hash = schema.tx_events(tx_hash).merkle_root();
+    schema.receipts_mut().put(
+        tx_hash,
+        Receipt::new(execution.gas_used, execution.returns, &events_hash),
+    );
+}
@@ -0,0 +1,293 @@
+use exonum::proto::ProtobufConvert;
+use protobuf::Message;
+
+use rlua::{Context, FromLua, ToLua, Value as LuaValue};
+use serde_json::{Map, Number, Value as JsonValue};
+
+use std::cmp::Ordering;
+
+use super::{proto, runner::BigInt};
+
+/// Maximum nesting of tables which can be converted from Lua.
+pub const MAX_DEPTH: usize = 32;
+
+/// Lua value which can be stored on chain.
+///
+/// Table entries are kept sorted by key, so equal tables always have the same
+/// binary representation. Lua strings are byte strings, so they are kept as bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Nil,
+    Bool(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(Value, Value)>),
+}
+
+impl Value {
+    /// Converts the value into JSON. Sequences become arrays, other tables become
+    /// objects with their keys converted to strings. Strings which are not valid UTF-8
+    /// are hex encoded.
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            Value::Nil => JsonValue::Null,
+            Value::Bool(b) => JsonValue::Bool(*b),
+            Value::Integer(i) => JsonValue::Number((*i).into()),
+            Value::Number(n) => Number::from_f64(*n).map_or(JsonValue::Null, JsonValue::Number),
+            Value::String(s) => JsonValue::String(string_to_json(s)),
+            Value::Table(entries) => {
+                let is_sequence = entries
+                    .iter()
+                    .enumerate()
+                    .all(|(i, (key, _))| *key == Value::Integer(i as i64 + 1));
+                if is_sequence && !entries.is_empty() {
+                    JsonValue::Array(entries.iter().map(|(_, v)| v.to_json()).collect())
+                } else {
+                    let object: Map<_, _> = entries
+                        .iter()
+                        .map(|(k, v)| (k.to_key_string(), v.to_json()))
+                        .collect();
+                    JsonValue::Object(object)
+                }
+            }
+        }
+    }
+
+    /// Converts JSON into a value. Arrays become sequences, objects become tables with
+    /// string keys.
+    pub fn from_json(json: &JsonValue) -> Self {
+        match json {
+            JsonValue::Null => Value::Nil,
+            JsonValue::Bool(b) => Value::Bool(*b),
+            JsonValue::Number(n) => match n.as_i64() {
+                Some(i) => Value::Integer(i),
+                None => Value::Number(n.as_f64().unwrap_or(0.0)),
+            },
+            JsonValue::String(s) => Value::from(s.as_str()),
+            JsonValue::Array(items) => Value::Table(
+                items
+                    .iter()
+                    .enumerate()
+                    .map(|(i, item)| (Value::Integer(i as i64 + 1), Value::from_json(item)))
+                    .filter(|(_, value)| *value != Value::Nil)
+                    .collect(),
+            ),
+            JsonValue::Object(object) => {
+                let mut entries: Vec<_> = object
+                    .iter()
+                    .map(|(key, value)| (Value::from(key.as_str()), Value::from_json(value)))
+                    .filter(|(_, value)| *value != Value::Nil)
+                    .collect();
+                entries.sort_by(|(a, _), (b, _)| a.cmp_keys(b));
+                Value::Table(entries)
+            }
+        }
+    }
+
+    /// Returns the size of the value in its binary representation.
+    pub fn byte_size(&self) -> u64 {
+        u64::from(self.to_pb().compute_size())
+    }
+
+    fn to_key_string(&self) -> String {
+        match self {
+            Value::Bool(b) => b.to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => string_to_json(s),
+            Value::Nil | Value::Table(_) => unreachable!("Invalid table key"),
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Nil => 0,
+            Value::Bool(_) => 1,
+            Value::Integer(_) => 2,
+            Value::Number(_) => 3,
+            Value::String(_) => 4,
+            Value::Table(_) => 5,
+        }
+    }
+
+    fn cmp_keys(&self, other: &Value) -> Ordering {
+        match (self, other) {
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Integer(a), Value::Integer(b)) => a.cmp(b),
+            (Value::Number(a), Value::Number(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            _ => self.rank().cmp(&other.rank()),
+        }
+    }
+
+    fn from_lua_nested(value: LuaValue, depth: usize) -> rlua::Result<Self> {
+        Ok(match value {
+            LuaValue::Nil => Value::Nil,
+            LuaValue::Boolean(b) => Value::Bool(b),
+            LuaValue::Integer(i) => Value::Integer(i),
+            LuaValue::Number(n) => Value::Number(n),
+            LuaValue::String(s) => Value::String(s.as_bytes().to_vec()),
+            LuaValue::Table(table) => {
+                if depth >= MAX_DEPTH {
+                    return Err(rlua::Error::RuntimeError(
+                        "table nesting is too deep".to_string(),
+                    ));
+                }
+
+                let mut entries = Vec::new();
+                for pair in table.pairs::<LuaValue, LuaValue>() {
+                    let (key, value) = pair?;
+                    let key = match key {
+                        LuaValue::Table(_) => {
+                            return Err(rlua::Error::RuntimeError(
+                                "tables cannot be used as keys".to_string(),
+                            ))
+                        }
+                        key => Self::from_lua_nested(key, depth + 1)?,
+                    };
+                    entries.push((key, Self::from_lua_nested(value, depth + 1)?));
+                }
+                entries.sort_by(|(a, _), (b, _)| a.cmp_keys(b));
+                Value::Table(entries)
+            }
+            LuaValue::UserData(ud) if ud.is::<BigInt>() => {
+                let bigint = *ud.borrow::<BigInt>()?;
+                Value::Integer(bigint.to_i64().ok_or_else(|| {
+                    rlua::Error::RuntimeError("bigint does not fit into an integer".to_string())
+                })?)
+            }
+            other => {
+                return Err(rlua::Error::FromLuaConversionError {
+                    from: other.type_name(),
+                    to: "Value",
+                    message: Some("only nil, booleans, numbers, strings and tables can be stored".to_string()),
+                })
+            }
+        })
+    }
+}
+
+fn string_to_json(s: &[u8]) -> String {
+    match std::str::from_utf8(s) {
+        Ok(s) => s.to_owned(),
+        Err(_) => hex::encode(s),
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Integer(value)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value.into_bytes())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.as_bytes().to_vec())
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(value: Vec<u8>) -> Self {
+        Value::String(value)
+    }
+}
+
+impl<'lua> FromLua<'lua> for Value {
+    fn from_lua(value: LuaValue<'lua>, _: Context<'lua>) -> rlua::Result<Self> {
+        Self::from_lua_nested(value, 0)
+    }
+}
+
+impl<'lua> ToLua<'lua> for Value {
+    fn to_lua(self, lua_ctx: Context<'lua>) -> rlua::Result<LuaValue<'lua>> {
+        Ok(match self {
+            Value::Nil => LuaValue::Nil,
+            Value::Bool(b) => LuaValue::Boolean(b),
+            Value::Integer(i) => LuaValue::Integer(i),
+            Value::Number(n) => LuaValue::Number(n),
+            Value::String(s) => LuaValue::String(lua_ctx.create_string(&s)?),
+            Value::Table(entries) => {
+                let table = lua_ctx.create_table()?;
+                for (key, value) in entries {
+                    table.raw_set(key, value)?;
+                }
+                LuaValue::Table(table)
+            }
+        })
+    }
+}
+
+impl ProtobufConvert for Value {
+    type ProtoStruct = proto::Value;
+
+    fn to_pb(&self) -> proto::Value {
+        let mut pb = proto::Value::new();
+        match self {
+            Value::Nil => {}
+            Value::Bool(b) => pb.set_boolean(*b),
+            Value::Integer(i) => pb.set_integer(*i),
+            Value::Number(n) => pb.set_number(*n),
+            Value::String(s) => pb.set_str(s.clone()),
+            Value::Table(entries) => {
+                let mut table = proto::Table::new();
+                table.set_entries(
+                    entries
+                        .iter()
+                        .map(|(key, value)| {
+                            let mut entry = proto::TableEntry::new();
+                            entry.set_key(key.to_pb());
+                            entry.set_value(value.to_pb());
+                            entry
+                        })
+                        .collect(),
+                );
+                pb.set_table(table);
+            }
+        }
+        pb
+    }
+
+    fn from_pb(mut pb: proto::Value) -> Result<Self, failure::Error> {
+        Ok(if pb.has_boolean() {
+            Value::Bool(pb.get_boolean())
+        } else if pb.has_integer() {
+            Value::Integer(pb.get_integer())
+        } else if pb.has_number() {
+            Value::Number(pb.get_number())
+        } else if pb.has_str() {
+            Value::String(pb.take_str())
+        } else if pb.has_table() {
+            let mut entries = Vec::new();
+            for mut entry in pb.take_table().take_entries().into_vec() {
+                let key = Value::from_pb(entry.take_key())?;
+                if let Value::Nil | Value::Table(_) = key {
+                    bail!("Invalid table key");
+                }
+                let value = Value::from_pb(entry.take_value())?;
+                entries.push((key, value));
+            }
+            Value::Table(entries)
+        } else {
+            Value::Nil
+        })
+    }
+}
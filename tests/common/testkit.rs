@@ -1,5 +1,8 @@
 use exonum::{
-    api::node::public::explorer::{TransactionQuery, TransactionResponse},
+    api::{
+        self,
+        node::public::explorer::{TransactionQuery, TransactionResponse},
+    },
     crypto::{self, Hash, PublicKey, SecretKey},
     messages::{self, RawTransaction, Signed},
 };
@@ -15,13 +18,28 @@ use exonum_lvm::{
         wallet::Wallet,
     },
     lvm::{
+        abi::Abi,
         service as lvm_service,
-        api::{ContractInfo, ContractQuery},
+        api::{
+            AddressInfo, AddressQuery, CodeInfo, CodeQuery, ContractEvents, ContractInfo, ContractQuery, ReceiptInfo,
+            ReceiptQuery, StateProof, StateQuery, TokenBalanceProof, TokenBalanceQuery, TokenHolder, TokenInfo,
+            TokenQuery, TxEvents, ViewQuery, ViewResult,
+        },
         contract::Contract,
-        transactions::{CreateContract, CallContract},
+        event::Event,
+        receipt::Receipt,
+        schema::state_key,
+        token::Token,
+        value::Value,
+        transactions::{
+            ApproveTokens, CallContract, CreateContract, TransferOwnership, TransferTokens, UpgradeContract,
+        },
     },
 };
 
+/// Gas limit used for contract calls unless a test sets its own.
+pub const GAS_LIMIT: u64 = 1_000_000;
+
 /// Wrapper for the cryptocurrency service API allowing to easily use it
 /// (compared to `TestKitApi` calls).
 pub struct CryptocurrencyApi {
@@ -94,6 +112,25 @@ impl CryptocurrencyApi {
 
     /// Asserts that the transaction with the given hash has a specified status.
     pub fn assert_tx_status(&self, tx_hash: Hash, expected_status: &serde_json::Value) {
+        assert_eq!(self.tx_status(tx_hash), *expected_status);
+    }
+
+    /// Asserts that a contract call failed with the given error, whose description
+    /// may be followed by the gas used by the call.
+    pub fn assert_tx_error(&self, tx_hash: Hash, code: u8, description: &str) {
+        let status = self.tx_status(tx_hash);
+        assert_eq!(status["type"], "error");
+        assert_eq!(status["code"], code);
+        let actual = status["description"].as_str().unwrap();
+        assert!(
+            actual == description || actual.starts_with(&format!("{} (gas used: ", description)),
+            "unexpected error description: {}",
+            actual
+        );
+    }
+
+    /// Returns the execution status of the transaction with the given hash.
+    pub fn tx_status(&self, tx_hash: Hash) -> serde_json::Value {
         let info: serde_json::Value = self
             .inner
             .public(ApiKind::Explorer)
@@ -102,18 +139,60 @@ impl CryptocurrencyApi {
             .unwrap();
 
         if let serde_json::Value::Object(mut info) = info {
-            let tx_status = info.remove("status").unwrap();
-            assert_eq!(tx_status, *expected_status);
+            info.remove("status").unwrap()
         } else {
             panic!("Invalid transaction info format, object expected");
         }
     }
 
     pub fn create_contract(&self, code: &str) -> (Signed<RawTransaction>, PublicKey) {
+        self.create_contract_with_args(code, vec![])
+    }
+
+    /// Creates a contract passing `args` to its `init` constructor.
+    pub fn create_contract_with_args(
+        &self,
+        code: &str,
+        args: Vec<&str>,
+    ) -> (Signed<RawTransaction>, PublicKey) {
         let (pubkey, key) = crypto::gen_keypair();
-        let (contract_pk, _) = crypto::gen_keypair();
+        self.create_contract_by(code, args, &pubkey, &key)
+    }
+
+    /// Creates a contract declaring the given ABI.
+    pub fn create_contract_with_abi(
+        &self,
+        code: &str,
+        args: Vec<&str>,
+        abi: &Abi,
+    ) -> (Signed<RawTransaction>, PublicKey) {
+        let (pubkey, key) = crypto::gen_keypair();
+        self.deploy_contract(code, args, abi, &pubkey, &key)
+    }
+
+    /// Creates a contract on behalf of the given author, returning its predicted address.
+    pub fn create_contract_by(
+        &self,
+        code: &str,
+        args: Vec<&str>,
+        pubkey: &PublicKey,
+        key: &SecretKey,
+    ) -> (Signed<RawTransaction>, PublicKey) {
+        self.deploy_contract(code, args, &Abi::default(), pubkey, key)
+    }
+
+    fn deploy_contract(
+        &self,
+        code: &str,
+        args: Vec<&str>,
+        abi: &Abi,
+        pubkey: &PublicKey,
+        key: &SecretKey,
+    ) -> (Signed<RawTransaction>, PublicKey) {
+        let contract_pk = self.next_contract_address(pubkey).address;
+        let args = args.iter().map(|s| s.to_string()).collect();
         // Create a pre-signed transaction
-        let tx = CreateContract::sign(&contract_pk, code, &pubkey, &key);
+        let tx = CreateContract::sign(code, &args, GAS_LIMIT, abi, pubkey, key);
 
         let data = messages::to_hex_string(&tx);
         let tx_info: TransactionResponse = self
@@ -126,6 +205,45 @@ impl CryptocurrencyApi {
         (tx, contract_pk)
     }
 
+    pub fn get_code(&self, code_hash: Hash) -> Option<String> {
+        let code_info = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&CodeQuery { code_hash })
+            .get::<CodeInfo>("v1/contracts/code")
+            .unwrap();
+
+        let code_proof = code_info.code_proof.check().unwrap();
+        let code = code_proof
+            .all_entries()
+            .find(|(ref k, _)| **k == code_hash)
+            .and_then(|tuple| tuple.1)
+            .cloned();
+        code
+    }
+
+    /// Returns code of the contract with the given public key.
+    pub fn get_contract_code(&self, pub_key: PublicKey) -> Option<String> {
+        let contract = self.get_contract(pub_key)?;
+        self.get_code(contract.code_hash)
+    }
+
+    pub fn get_abi(&self, pub_key: PublicKey) -> Abi {
+        self.inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&ContractQuery { pub_key })
+            .get("v1/contracts/abi")
+            .unwrap()
+    }
+
+    pub fn next_contract_address(&self, author: &PublicKey) -> AddressInfo {
+        self.inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&AddressQuery { author: *author })
+            .get("v1/contracts/address")
+            .unwrap()
+    }
+
     pub fn get_contract(&self, pub_key: PublicKey) -> Option<Contract> {
         let contract_info = self
             .inner
@@ -144,10 +262,20 @@ impl CryptocurrencyApi {
     }
 
     pub fn call_contract(&self, contract_pk: &PublicKey, fn_name: &str, args: Vec<&str>) -> Signed<RawTransaction> {
+        self.call_contract_with_gas(contract_pk, fn_name, args, GAS_LIMIT)
+    }
+
+    pub fn call_contract_with_gas(
+        &self,
+        contract_pk: &PublicKey,
+        fn_name: &str,
+        args: Vec<&str>,
+        gas_limit: u64,
+    ) -> Signed<RawTransaction> {
         let (pubkey, key) = crypto::gen_keypair();
 
         let args = args.iter().map(|s| s.to_string()).collect();
-        let tx = CallContract::sign(&contract_pk, fn_name, &args, &pubkey, &key);
+        let tx = CallContract::sign(&contract_pk, fn_name, &args, gas_limit, 0, &pubkey, &key);
 
         let data = messages::to_hex_string(&tx);
         let tx_info: TransactionResponse = self
@@ -159,6 +287,286 @@ impl CryptocurrencyApi {
         assert_eq!(tx_info.tx_hash, tx.hash());
         tx
     }
+
+    /// Calls the contract on behalf of the given wallet, paying `value` to the contract.
+    pub fn call_contract_with_value(
+        &self,
+        contract_pk: &PublicKey,
+        fn_name: &str,
+        args: Vec<&str>,
+        value: u64,
+        pubkey: &PublicKey,
+        key: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        let args = args.iter().map(|s| s.to_string()).collect();
+        let tx = CallContract::sign(contract_pk, fn_name, &args, GAS_LIMIT, value, pubkey, key);
+        self.post_tx(&tx);
+        tx
+    }
+
+    pub fn upgrade_contract(
+        &self,
+        contract_pk: &PublicKey,
+        code: &str,
+        owner_pk: &PublicKey,
+        owner_sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        let tx = UpgradeContract::sign(
+            contract_pk,
+            code,
+            GAS_LIMIT,
+            &Abi::default(),
+            owner_pk,
+            owner_sk,
+        );
+        self.post_tx(&tx);
+        tx
+    }
+
+    pub fn transfer_ownership(
+        &self,
+        contract_pk: &PublicKey,
+        new_owner: &PublicKey,
+        owner_pk: &PublicKey,
+        owner_sk: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        let tx = TransferOwnership::sign(contract_pk, new_owner, owner_pk, owner_sk);
+        self.post_tx(&tx);
+        tx
+    }
+
+    pub fn transfer_tokens(
+        &self,
+        token: &PublicKey,
+        to: &PublicKey,
+        amount: u64,
+        pubkey: &PublicKey,
+        key: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        let tx = TransferTokens::sign(token, to, amount, pubkey, key);
+        self.post_tx(&tx);
+        tx
+    }
+
+    pub fn approve_tokens(
+        &self,
+        token: &PublicKey,
+        spender: &PublicKey,
+        amount: u64,
+        pubkey: &PublicKey,
+        key: &SecretKey,
+    ) -> Signed<RawTransaction> {
+        let tx = ApproveTokens::sign(token, spender, amount, pubkey, key);
+        self.post_tx(&tx);
+        tx
+    }
+
+    fn post_tx(&self, tx: &Signed<RawTransaction>) {
+        let data = messages::to_hex_string(tx);
+        let tx_info: TransactionResponse = self
+            .inner
+            .public(ApiKind::Explorer)
+            .query(&json!({ "tx_body": data }))
+            .post("v1/transactions")
+            .unwrap();
+        assert_eq!(tx_info.tx_hash, tx.hash());
+    }
+
+    /// Reads `key` of the contract state and checks its proof against the contract.
+    pub fn get_state(&self, pub_key: &PublicKey, key: &str) -> Option<Value> {
+        let state_proof = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&StateQuery {
+                pub_key: *pub_key,
+                key: key.to_string(),
+            })
+            .get::<StateProof>("v1/contracts/state")
+            .unwrap();
+
+        let to_contract = state_proof.to_contract.check().unwrap();
+        let contract = to_contract
+            .all_entries()
+            .find(|(ref k, _)| *k == pub_key)
+            .and_then(|tuple| tuple.1)
+            .cloned()?;
+
+        let to_entry = state_proof.to_entry.check().unwrap();
+        assert_eq!(to_entry.merkle_root(), contract.state_hash);
+
+        let key_hash = state_key(key);
+        let entry = to_entry
+            .all_entries()
+            .find(|(ref k, _)| **k == key_hash)
+            .and_then(|tuple| tuple.1)
+            .cloned();
+        entry.map(|entry| {
+            assert_eq!(entry.key, key);
+            entry.value
+        })
+    }
+
+    /// Executes a contract function against the latest state without committing it.
+    pub fn call_view(
+        &self,
+        pub_key: &PublicKey,
+        fn_name: &str,
+        args: Vec<&str>,
+    ) -> api::Result<ViewResult> {
+        let query = ViewQuery {
+            pub_key: *pub_key,
+            fn_name: fn_name.to_string(),
+            args: args.iter().map(|s| s.to_string()).collect(),
+            caller: None,
+        };
+        self.inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&query)
+            .post("v1/contracts/call")
+    }
+
+    /// Returns events emitted by the contract, checking them against its `events_hash`.
+    pub fn get_contract_events(&self, pub_key: &PublicKey) -> Vec<Event> {
+        let info = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&ContractQuery { pub_key: *pub_key })
+            .get::<ContractEvents>("v1/contracts/events")
+            .unwrap();
+
+        let contract = info
+            .to_contract
+            .check()
+            .unwrap()
+            .all_entries()
+            .find(|(ref k, _)| **k == *pub_key)
+            .and_then(|tuple| tuple.1)
+            .cloned();
+        match (contract, info.proof) {
+            (Some(contract), Some(proof)) => {
+                assert_eq!(proof.merkle_root(), contract.events_hash);
+                assert_eq!(info.events.len() as u64, contract.events_len);
+            }
+            (Some(contract), None) => {
+                assert_eq!(contract.events_hash, Hash::zero());
+                assert_eq!(contract.events_len, 0);
+            }
+            (None, proof) => assert!(proof.is_none()),
+        }
+        info.events
+    }
+
+    /// Returns events emitted by the transaction, checking them against its receipt.
+    pub fn get_tx_events(&self, tx_hash: Hash) -> Vec<Event> {
+        let info = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&ReceiptQuery { tx_hash })
+            .get::<TxEvents>("v1/contracts/tx_events")
+            .unwrap();
+
+        let receipt = info
+            .to_receipt
+            .check()
+            .unwrap()
+            .all_entries()
+            .find(|(ref k, _)| **k == tx_hash)
+            .and_then(|tuple| tuple.1)
+            .cloned();
+        let events_hash = info.proof.map_or_else(Hash::zero, |proof| proof.merkle_root());
+        match receipt {
+            Some(receipt) => assert_eq!(receipt.events_hash, events_hash),
+            None => assert!(info.events.is_empty()),
+        }
+        info.events
+    }
+
+    pub fn get_receipt(&self, tx_hash: Hash) -> Option<Receipt> {
+        let receipt_info = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&ReceiptQuery { tx_hash })
+            .get::<ReceiptInfo>("v1/contracts/receipt")
+            .unwrap();
+
+        let receipt_proof = receipt_info.receipt_proof.check().unwrap();
+        let receipt = receipt_proof
+            .all_entries()
+            .find(|(ref k, _)| **k == tx_hash)
+            .and_then(|tuple| tuple.1)
+            .cloned();
+        receipt
+    }
+
+    pub fn get_tokens(&self) -> Vec<Token> {
+        self.inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .get("v1/tokens")
+            .unwrap()
+    }
+
+    /// Returns the token issued by the contract, checking its history against `history_hash`.
+    pub fn get_token(&self, pub_key: &PublicKey) -> Option<Token> {
+        let info = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&TokenQuery { pub_key: *pub_key })
+            .get::<TokenInfo>("v1/tokens/info")
+            .unwrap();
+
+        let token = info
+            .to_token
+            .check()
+            .unwrap()
+            .all_entries()
+            .find(|(ref k, _)| **k == *pub_key)
+            .and_then(|tuple| tuple.1)
+            .cloned()?;
+        let history = info.token_history.expect("No history of an existing token");
+        assert_eq!(history.proof.merkle_root(), token.history_hash);
+        assert_eq!(history.transactions.len() as u64, token.history_len);
+        Some(token)
+    }
+
+    /// Returns the balance of the holder, checking its proof against the token `balances_hash`.
+    pub fn get_token_balance(&self, token: &PublicKey, holder: &PublicKey) -> u64 {
+        let proof = self
+            .inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&TokenBalanceQuery {
+                token: *token,
+                holder: *holder,
+            })
+            .get::<TokenBalanceProof>("v1/tokens/balance")
+            .unwrap();
+
+        let token = proof
+            .to_token
+            .check()
+            .unwrap()
+            .all_entries()
+            .find(|(ref k, _)| *k == token)
+            .and_then(|tuple| tuple.1)
+            .cloned()
+            .expect("No token persisted");
+
+        let to_balance = proof.to_balance.check().unwrap();
+        assert_eq!(to_balance.merkle_root(), token.balances_hash);
+        to_balance
+            .all_entries()
+            .find(|(ref k, _)| *k == holder)
+            .and_then(|tuple| tuple.1)
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    pub fn get_token_holders(&self, token: &PublicKey) -> Vec<TokenHolder> {
+        self.inner
+            .public(ApiKind::Service(lvm_service::SERVICE_NAME))
+            .query(&TokenQuery { pub_key: *token })
+            .get("v1/tokens/holders")
+            .unwrap()
+    }
 }
 
 /// Creates a testkit together with the API wrapper defined above.
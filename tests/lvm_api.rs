@@ -0,0 +1,129 @@
+//! Tests of the LVM service HTTP API.
+
+#[macro_use]
+extern crate serde_json;
+
+use exonum_lvm::lvm::{
+    abi::{Abi, FunctionAbi, ValueType},
+    value::Value,
+};
+
+use common::{testkit::create_testkit, ALICE_NAME};
+
+mod common;
+
+#[test]
+fn view_returns_values() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function set(value)
+            state["value"] = value
+        end
+
+        function doubled()
+            return state["value"] * 2, { label = "value", items = { 1, 2 } }
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "set", vec!["21"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "doubled", vec![]).unwrap();
+    assert_eq!(
+        result.returns,
+        vec![json!(42), json!({ "label": "value", "items": [1, 2] })]
+    );
+}
+
+#[test]
+fn view_rejects_writes() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, _) = api.create_wallet(ALICE_NAME);
+
+    let code = r#"
+        function touch()
+            state["touched"] = "yes"
+        end
+
+        function pay(to)
+            transfer(to, 1)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    assert!(api.call_view(&contract_pub, "touch", vec![]).is_err());
+    assert!(api
+        .call_view(&contract_pub, "pay", vec![&tx_alice.author().to_hex()])
+        .is_err());
+
+    assert!(api.get_state(&contract_pub, "touched").is_none());
+    let wallet = api.get_wallet(contract_pub).unwrap();
+    assert_eq!(wallet.balance, 100);
+}
+
+#[test]
+fn abi_types_arguments() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function set(value)
+            state["value"] = value
+        end
+
+        function add(a, b)
+            return a + b
+        end
+
+        function broken()
+            return "not a number"
+        end
+    "#;
+    let abi = Abi::new(vec![
+        FunctionAbi::new("set", vec![ValueType::Integer], vec![], false),
+        FunctionAbi::new(
+            "add",
+            vec![ValueType::Integer, ValueType::Number],
+            vec![ValueType::Number],
+            true,
+        ),
+        FunctionAbi::new("broken", vec![], vec![ValueType::Number], true),
+    ]);
+    let (tx, contract_pub) = api.create_contract_with_abi(code, vec![], &abi);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_abi(contract_pub), abi);
+
+    let tx = api.call_contract(&contract_pub, "set", vec!["21"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_state(&contract_pub, "value"), Some(Value::Integer(21)));
+
+    for (fn_name, args) in vec![("set", vec!["abc"]), ("set", vec![]), ("unknown", vec![])] {
+        let tx = api.call_contract(&contract_pub, fn_name, args);
+        testkit.create_block();
+        let info = api.tx_status(tx.hash());
+        assert_eq!(info["type"], "error");
+        assert_eq!(info["code"], 10);
+    }
+
+    let result = api.call_view(&contract_pub, "add", vec!["2", "0.5"]).unwrap();
+    assert_eq!(result.returns, vec![json!(2.5)]);
+    assert!(api.call_view(&contract_pub, "add", vec!["2", "x"]).is_err());
+    assert!(api.call_view(&contract_pub, "set", vec!["1"]).is_err());
+    assert!(api.call_view(&contract_pub, "broken", vec![]).is_err());
+
+    // Every declared function must be defined by the code.
+    let abi = Abi::new(vec![FunctionAbi::new("missing", vec![], vec![], true)]);
+    let (tx, _) = api.create_contract_with_abi(code, vec![], &abi);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(info["code"], 10);
+}
@@ -0,0 +1,389 @@
+//! Tests of the Lua environment available to contracts.
+
+#[macro_use]
+extern crate serde_json;
+
+use exonum::crypto;
+use exonum_lvm::lvm::value::Value;
+
+use common::testkit::create_testkit;
+
+mod common;
+
+#[test]
+fn forbidden_globals_are_absent() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function check()
+            return {
+                require = type(require),
+                package = type(package),
+                dofile = type(dofile),
+                loadfile = type(loadfile),
+                collectgarbage = type(collectgarbage),
+                print = type(print),
+                io = type(io),
+                os = type(os),
+                debug = type(debug),
+                dump = type(string.dump),
+                randomseed = type(math.randomseed),
+            }
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "check", vec![]).unwrap();
+    let globals = result.returns[0].as_object().unwrap();
+    assert_eq!(globals.len(), 11);
+    for (name, ty) in globals {
+        assert_eq!(ty, "nil", "`{}` is available to contracts", name);
+    }
+}
+
+#[test]
+fn load_accepts_only_text() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function text()
+            return load("return 1 + 1")()
+        end
+
+        function with_env()
+            return load("return x", "chunk", "t", { x = 3 })()
+        end
+
+        function binary()
+            local chunk, err = load(string.char(27) .. "Lua", "chunk", "b")
+            return chunk == nil, err
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "text", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!(2)]);
+    let result = api.call_view(&contract_pub, "with_env", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!(3)]);
+
+    let result = api.call_view(&contract_pub, "binary", vec![]).unwrap();
+    assert_eq!(result.returns[0], json!(true));
+    assert!(result.returns[1].as_str().unwrap().contains("binary"));
+}
+
+#[test]
+fn random_is_deterministic() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function roll()
+            state["roll"] = math.random(1, 6)
+            return math.random(1, 6), math.random(10), math.random()
+        end
+
+        function peek()
+            return math.random(1, 1000000), math.random()
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // Views at the same height share the seed.
+    let first = api.call_view(&contract_pub, "peek", vec![]).unwrap();
+    let second = api.call_view(&contract_pub, "peek", vec![]).unwrap();
+    assert_eq!(first.returns, second.returns);
+
+    let tx = api.call_contract(&contract_pub, "roll", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let returns = api.get_receipt(tx.hash()).unwrap().returns;
+    match (&returns[0], &returns[1], &returns[2]) {
+        (Value::Integer(die), Value::Integer(tenth), Value::Number(float)) => {
+            assert!(*die >= 1 && *die <= 6);
+            assert!(*tenth >= 1 && *tenth <= 10);
+            assert!(*float >= 0.0 && *float < 1.0);
+        }
+        other => panic!("Unexpected returns: {:?}", other),
+    }
+    match api.get_state(&contract_pub, "roll") {
+        Some(Value::Integer(die)) => assert!(die >= 1 && die <= 6),
+        other => panic!("Unexpected state: {:?}", other),
+    }
+}
+
+#[test]
+fn tostring_is_deterministic() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function names()
+            local first, second = {}, {}
+            return tostring(first), tostring(second), tostring(first),
+                string.format("%s and %s", second, first), tostring(bigint(5))
+        end
+
+        function fail()
+            error({})
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // Reference values are numbered in the order they are printed, instead of showing addresses.
+    let result = api.call_view(&contract_pub, "names", vec![]).unwrap();
+    assert_eq!(
+        result.returns,
+        vec![
+            json!("table: 1"),
+            json!("table: 2"),
+            json!("table: 1"),
+            json!("table: 2 and table: 1"),
+            json!("5"),
+        ]
+    );
+
+    let tx = api.call_contract(&contract_pub, "fail", vec![]);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["code"], 2);
+    let description = info["description"].as_str().unwrap();
+    assert!(description.contains("table: ?"));
+    assert!(!description.contains("0x"));
+}
+
+#[test]
+fn iteration_is_sorted() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function order()
+            local t = { "one", "two", b = 1, a = 2, [10] = 3, [2.5] = 4, [true] = 5, [false] = 6, z = 7 }
+            local with_pairs, with_next = {}, {}
+            for k in pairs(t) do
+                with_pairs[#with_pairs + 1] = tostring(k)
+            end
+            local k = next(t)
+            while k ~= nil do
+                with_next[#with_next + 1] = tostring(k)
+                k = next(t, k)
+            end
+            return table.concat(with_pairs, ","), table.concat(with_next, ",")
+        end
+
+        function remove()
+            local t = { a = 1, b = 2, c = 3 }
+            local seen = {}
+            for k in pairs(t) do
+                seen[#seen + 1] = k
+                t.b = nil
+            end
+            return table.concat(seen, ",")
+        end
+
+        function custom()
+            local t = setmetatable({}, {
+                __pairs = function(t)
+                    return function(_, k)
+                        if k == nil then
+                            return 1, "custom"
+                        end
+                    end, t, nil
+                end,
+            })
+            for _, v in pairs(t) do
+                return v
+            end
+        end
+
+        function table_keys()
+            local ok, err = pcall(next, { [{}] = 1 })
+            return ok, string.find(err, "cannot iterate") ~= nil
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "order", vec![]).unwrap();
+    let expected = json!("1,2,2.5,10,a,b,z,false,true");
+    assert_eq!(result.returns, vec![expected.clone(), expected]);
+
+    // Entries removed during the iteration are skipped.
+    let result = api.call_view(&contract_pub, "remove", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!("a,c")]);
+
+    let result = api.call_view(&contract_pub, "custom", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!("custom")]);
+
+    // Keys without a defined order cannot be iterated over.
+    let result = api.call_view(&contract_pub, "table_keys", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!(false), json!(true)]);
+}
+
+#[test]
+fn crypto_primitives() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function hash(data)
+            return sha256(data)
+        end
+
+        function verify(pub_key, data, signature)
+            return verify_signature(pub_key, data, signature)
+        end
+
+        function roundtrip(data)
+            local encoded = hex_encode(data)
+            return encoded, hex_decode(encoded) == data
+        end
+
+        function hash_large()
+            return sha256(string.rep("a", 64 * 1024 + 1))
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "hash", vec!["abc"]).unwrap();
+    assert_eq!(
+        result.returns,
+        vec![json!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")]
+    );
+
+    let (pub_key, secret_key) = crypto::gen_keypair();
+    let signature = crypto::sign(b"voucher", &secret_key);
+    let pub_key = hex::encode(&pub_key);
+    let signature = hex::encode(&signature);
+    let result = api
+        .call_view(&contract_pub, "verify", vec![&pub_key, "voucher", &signature])
+        .unwrap();
+    assert_eq!(result.returns, vec![json!(true)]);
+    let result = api
+        .call_view(&contract_pub, "verify", vec![&pub_key, "forged", &signature])
+        .unwrap();
+    assert_eq!(result.returns, vec![json!(false)]);
+    assert!(api
+        .call_view(&contract_pub, "verify", vec![&pub_key, "voucher", "zz"])
+        .is_err());
+
+    let result = api.call_view(&contract_pub, "roundtrip", vec!["lvm"]).unwrap();
+    assert_eq!(result.returns, vec![json!("6c766d"), json!(true)]);
+
+    assert!(api.call_view(&contract_pub, "hash_large", vec![]).is_err());
+}
+
+#[test]
+fn library_calls_are_metered() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function patterns()
+            return string.match == nil, string.gmatch == nil, string.gsub == nil
+        end
+
+        function find()
+            local backtracking = string.find(string.rep("a", 40), string.rep("a*", 40) .. "b", 1, true)
+            local pattern_ok = pcall(string.find, "a1b", "%d+")
+            return backtracking == nil, pattern_ok, string.find("a.b", ".", 1, true), string.find("a1b", "1")
+        end
+
+        function rep()
+            return #string.rep("", 1000000000)
+        end
+
+        function move()
+            return #table.move({}, 1, 1000000000000, 2)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let result = api.call_view(&contract_pub, "patterns", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!(true), json!(true), json!(true)]);
+
+    // Patterns are rejected rather than searched for as plain text.
+    let result = api.call_view(&contract_pub, "find", vec![]).unwrap();
+    assert_eq!(
+        result.returns,
+        vec![json!(true), json!(false), json!(2), json!(2), json!(2)]
+    );
+
+    // Both would run unmetered for minutes inside a single library call.
+    assert!(api.call_view(&contract_pub, "rep", vec![]).is_err());
+    assert!(api.call_view(&contract_pub, "move", vec![]).is_err());
+}
+
+#[test]
+fn oversized_ranges_do_not_refund_gas() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function move()
+            while true do
+                pcall(table.move, {}, math.mininteger + 1000, math.maxinteger, 1)
+            end
+        end
+
+        function concat()
+            while true do
+                pcall(table.concat, {}, "", math.mininteger + 1000, math.maxinteger)
+            end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    for fn_name in &["move", "concat"] {
+        let tx = api.call_contract(&contract_pub, fn_name, vec![]);
+        testkit.create_block();
+        api.assert_tx_error(tx.hash(), 3, "Out of gas");
+    }
+}
+
+#[test]
+fn allocations_are_metered() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        local s = string.rep("a", 4 * 1024 * 1024)
+
+        function concat()
+            while true do
+                local t = s .. s
+            end
+        end
+
+        function upper()
+            while true do
+                local t = s:upper()
+            end
+        end
+
+        function scan()
+            while true do
+                utf8.len(s)
+            end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // Each iteration takes a few instructions, but copies or scans megabytes.
+    for fn_name in &["concat", "upper", "scan"] {
+        let tx = api.call_contract(&contract_pub, fn_name, vec![]);
+        testkit.create_block();
+        api.assert_tx_error(tx.hash(), 3, "Out of gas");
+    }
+}
@@ -0,0 +1,180 @@
+//! Tests of the token ledger available to contracts.
+
+#[macro_use]
+extern crate serde_json;
+
+use exonum::crypto;
+use exonum_lvm::lvm::{token::MAX_TOKEN_SUPPLY, value::Value};
+
+use common::{testkit::create_testkit, ALICE_NAME, BOB_NAME};
+
+mod common;
+
+const TOKEN_CODE: &str = r#"
+    function init(supply)
+        token_create("Test token", "TST", 2)
+        token_mint(msg.caller, supply)
+    end
+
+    -- Takes tokens approved by the caller and burns them.
+    function redeem(amount)
+        token_transfer_from(msg.contract, msg.caller, msg.contract, amount)
+        token_burn(amount)
+        return token_supply(msg.contract), token_balance(msg.contract, msg.caller)
+    end
+"#;
+
+#[test]
+fn token_lifecycle() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, alice_key) = api.create_wallet(ALICE_NAME);
+    let (tx_bob, bob_key) = api.create_wallet(BOB_NAME);
+    let alice = tx_alice.author();
+    let bob = tx_bob.author();
+
+    let (tx, token_pub) = api.create_contract_by(TOKEN_CODE, vec!["1000"], &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let token = api.get_token(&token_pub).unwrap();
+    assert_eq!(token.name, "Test token");
+    assert_eq!(token.symbol, "TST");
+    assert_eq!(token.decimals, 2);
+    assert_eq!(token.total_supply, 1000);
+    assert_eq!(api.get_token_balance(&token_pub, &alice), 1000);
+    let tokens = api.get_tokens();
+    assert_eq!(tokens.len(), 1);
+    assert_eq!(tokens[0].pub_key, token_pub);
+
+    let tx = api.transfer_tokens(&token_pub, &bob, 300, &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_token_balance(&token_pub, &alice), 700);
+    assert_eq!(api.get_token_balance(&token_pub, &bob), 300);
+    assert_eq!(api.get_token_holders(&token_pub).len(), 2);
+
+    let tx = api.transfer_tokens(&token_pub, &alice, 500, &bob, &bob_key);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({ "type": "error", "code": 15, "description": "Insufficient token amount" }),
+    );
+
+    let tx = api.approve_tokens(&token_pub, &token_pub, 200, &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract_with_value(&token_pub, "redeem", vec!["150"], 0, &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let receipt = api.get_receipt(tx.hash()).unwrap();
+    assert_eq!(
+        receipt.returns,
+        vec![Value::Integer(850), Value::Integer(550)]
+    );
+    assert_eq!(api.get_token(&token_pub).unwrap().total_supply, 850);
+    assert_eq!(api.get_token_balance(&token_pub, &token_pub), 0);
+
+    // Only 50 tokens of the allowance are left.
+    let tx = api.call_contract_with_value(&token_pub, "redeem", vec!["100"], 0, &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 16, "Insufficient token allowance");
+    assert_eq!(api.get_token_balance(&token_pub, &alice), 550);
+}
+
+#[test]
+fn host_functions_are_not_entry_points() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, alice_key) = api.create_wallet(ALICE_NAME);
+    let alice = tx_alice.author();
+
+    let (tx, token_pub) = api.create_contract_by(TOKEN_CODE, vec!["1000"], &alice, &alice_key);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let alice_hex = alice.to_hex();
+    let token_hex = token_pub.to_hex();
+    let calls = vec![
+        ("token_mint", vec![alice_hex.as_str(), "1000"]),
+        ("transfer", vec![alice_hex.as_str(), "1"]),
+        ("call", vec![token_hex.as_str(), "redeem", "1"]),
+        ("emit", vec!["forged", "1"]),
+    ];
+    for (fn_name, args) in calls {
+        let tx = api.call_contract_with_value(&token_pub, fn_name, args, 0, &alice, &alice_key);
+        testkit.create_block();
+        api.assert_tx_error(
+            tx.hash(),
+            10,
+            &format!("function `{}` is not defined by the contract", fn_name),
+        );
+    }
+    assert_eq!(api.get_token(&token_pub).unwrap().total_supply, 1000);
+    assert!(api.get_contract_events(&token_pub).is_empty());
+}
+
+#[test]
+fn token_errors() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function create()
+            token_create("Test token", "TST", 0)
+        end
+
+        function mint(amount)
+            token_mint(msg.caller, amount)
+        end
+
+        function supply(token)
+            return token_supply(token)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert!(api.get_token(&contract_pub).is_none());
+
+    let tx = api.call_contract(&contract_pub, "mint", vec!["10"]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 13, "Token doesn't exist");
+
+    let tx = api.call_contract(&contract_pub, "create", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "create", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 14, "Token already exists");
+
+    let tx = api.call_contract(&contract_pub, "mint", vec!["-1"]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 12, "Invalid currency amount");
+
+    let tx = api.call_contract(&contract_pub, "mint", vec![&u64::max_value().to_string()]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 17, "Token supply overflow");
+
+    // The supply is capped so that amounts stay Lua integers.
+    let max_supply = MAX_TOKEN_SUPPLY.to_string();
+    let tx = api.call_contract(&contract_pub, "mint", vec![&max_supply]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let result = api
+        .call_view(&contract_pub, "supply", vec![&contract_pub.to_hex()])
+        .unwrap();
+    assert_eq!(result.returns, vec![json!(MAX_TOKEN_SUPPLY)]);
+
+    let tx = api.call_contract(&contract_pub, "mint", vec!["1"]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 17, "Token supply overflow");
+
+    // Views cannot change the ledger.
+    assert!(api.call_view(&contract_pub, "mint", vec!["1"]).is_err());
+
+    let (stranger, _) = crypto::gen_keypair();
+    let result = api
+        .call_view(&contract_pub, "supply", vec![&stranger.to_hex()])
+        .unwrap();
+    assert_eq!(result.returns, vec![json!(null)]);
+}
@@ -1,7 +1,13 @@
 #[macro_use]
 extern crate serde_json;
 
-use common::{testkit::create_testkit, ALICE_NAME};
+use exonum::{blockchain, crypto};
+use exonum_lvm::lvm::{config::DEFAULT_MAX_GAS, schema::contract_address, value::Value};
+
+use common::{
+    testkit::{create_testkit, GAS_LIMIT},
+    ALICE_NAME,
+};
 
 mod common;
 
@@ -9,7 +15,7 @@ mod common;
 fn create_contract() {
     let (mut testkit, api) = create_testkit();
 
-    let code = "some code";
+    let code = "function f() end";
     let (tx, contract_pub) = api.create_contract(code);
     testkit.create_block();
     api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
@@ -18,7 +24,57 @@ fn create_contract() {
     assert!(contract.is_some());
     let contract = contract.unwrap();
     assert_eq!(contract.pub_key, contract_pub);
-    assert_eq!(contract.code, code);
+    assert_eq!(api.get_code(contract.code_hash), Some(code.to_string()));
+}
+
+#[test]
+fn contracts_share_code() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = "function f() end";
+    let (tx_first, first_pub) = api.create_contract(code);
+    let (tx_second, second_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_first.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_second.hash(), &json!({ "type": "success" }));
+
+    let first = api.get_contract(first_pub).unwrap();
+    let second = api.get_contract(second_pub).unwrap();
+    assert_eq!(first.code_hash, second.code_hash);
+    assert_eq!(first.code_hash, crypto::hash(code.as_bytes()));
+}
+
+#[test]
+fn create_contract_invalid_code() {
+    let (mut testkit, api) = create_testkit();
+
+    let (tx, contract_pub) = api.create_contract("some code");
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(info["code"], 9);
+    assert!(info["description"].as_str().unwrap().contains("syntax error"));
+    assert!(api.get_contract(contract_pub).is_none());
+}
+
+#[test]
+fn contract_address_derived_from_author() {
+    let (mut testkit, api) = create_testkit();
+    let (pubkey, key) = crypto::gen_keypair();
+
+    let (tx_first, first_pub) = api.create_contract_by("function f() end", vec![], &pubkey, &key);
+    testkit.create_block();
+    api.assert_tx_status(tx_first.hash(), &json!({ "type": "success" }));
+    assert_eq!(first_pub, contract_address(&pubkey, 0));
+
+    let (tx_second, second_pub) = api.create_contract_by("function f() end", vec![], &pubkey, &key);
+    testkit.create_block();
+    api.assert_tx_status(tx_second.hash(), &json!({ "type": "success" }));
+    assert_eq!(second_pub, contract_address(&pubkey, 1));
+
+    assert!(api.get_contract(first_pub).is_some());
+    assert!(api.get_contract(second_pub).is_some());
+    assert_eq!(api.next_contract_address(&pubkey).nonce, 2);
 }
 
 #[test]
@@ -56,15 +112,77 @@ fn contract_changes_state() {
 
     let contract_before = api.get_contract(contract_pub);
     assert!(contract_before.is_some());
-    let contract_before = contract_before.unwrap();
-    assert!(contract_before.state.get("hello").is_none());
+    assert!(api.get_state(&contract_pub, "hello").is_none());
 
     let tx = api.call_contract(&contract_pub, "greet", vec!["lvm"]);
     testkit.create_block();
     api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
 
-    let contract_after = api.get_contract(contract_pub).unwrap();
-    assert_eq!(contract_after.state.get("hello"), Some(&"lvm".to_string()));
+    assert_eq!(api.get_state(&contract_pub, "hello"), Some(Value::from("lvm")));
+}
+
+#[test]
+fn contract_stores_binary_strings() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function store()
+            state["raw"] = hex_decode("ff00")
+        end
+
+        function read()
+            return hex_encode(state["raw"]), #state["raw"]
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "store", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    assert_eq!(api.get_state(&contract_pub, "raw"), Some(Value::from(vec![0xff, 0x00])));
+    let result = api.call_view(&contract_pub, "read", vec![]).unwrap();
+    assert_eq!(result.returns, vec![json!("ff00"), json!(2)]);
+}
+
+#[test]
+fn contract_state_is_limited() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function long_key()
+            state[string.rep("k", 257)] = 1
+        end
+
+        function big_value()
+            state["big"] = string.rep("v", 64 * 1024)
+        end
+
+        function many_keys()
+            for i = 1, 1000000 do
+                local _ = state["key" .. i]
+            end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "long_key", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "state key is too large");
+
+    let tx = api.call_contract(&contract_pub, "big_value", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "state value is too large");
+    assert!(api.get_state(&contract_pub, "big").is_none());
+
+    // Every accessed key is charged for, so the number of touched keys is bounded.
+    let tx = api.call_contract(&contract_pub, "many_keys", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 3, "Out of gas");
 }
 
 #[test]
@@ -72,7 +190,7 @@ fn contract_persist_state() {
     let (mut testkit, api) = create_testkit();
 
     let code = r#"
-        function reset()
+        function init()
             state["counter"] = 0
         end
 
@@ -83,10 +201,7 @@ fn contract_persist_state() {
     let (tx, contract_pub) = api.create_contract(code);
     testkit.create_block();
     api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
-
-    let tx = api.call_contract(&contract_pub, "reset", vec![]);
-    testkit.create_block();
-    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_state(&contract_pub, "counter"), Some(Value::Integer(0)));
 
     for _ in 0..2 {
         let tx = api.call_contract(&contract_pub, "increase", vec![]);
@@ -94,8 +209,7 @@ fn contract_persist_state() {
         api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
     }
 
-    let contract = api.get_contract(contract_pub).unwrap();
-    assert_eq!(contract.state.get("counter"), Some(&"2.0".to_string()));
+    assert_eq!(api.get_state(&contract_pub, "counter"), Some(Value::Integer(2)));
 }
 
 #[test]
@@ -131,3 +245,896 @@ fn contract_do_transfer() {
     let wallet = api.get_wallet(contract_pub).unwrap();
     assert_eq!(wallet.balance, 95);
 }
+
+#[test]
+fn contract_out_of_gas() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function spin()
+            while true do end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract_with_gas(&contract_pub, "spin", vec![], 10_000);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({ "type": "error", "code": 3, "description": "Out of gas (gas used: 10000)" }),
+    );
+    assert!(api.get_receipt(tx.hash()).is_none());
+
+    let tx = api.call_contract_with_gas(&contract_pub, "spin", vec![], DEFAULT_MAX_GAS + 1);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({ "type": "error", "code": 18, "description": "Gas limit exceeds the maximum" }),
+    );
+}
+
+#[test]
+fn contract_reports_gas_used() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function count(n)
+            local sum = 0
+            for i = 1, n do
+                sum = sum + i
+            end
+            state["sum"] = sum
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx_small = api.call_contract(&contract_pub, "count", vec!["10"]);
+    let tx_big = api.call_contract(&contract_pub, "count", vec!["10000"]);
+    testkit.create_block();
+    api.assert_tx_status(tx_small.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_big.hash(), &json!({ "type": "success" }));
+
+    let small = api.get_receipt(tx_small.hash()).unwrap();
+    let big = api.get_receipt(tx_big.hash()).unwrap();
+    assert!(small.gas_used < big.gas_used);
+    assert!(big.gas_used <= GAS_LIMIT);
+}
+
+#[test]
+fn contract_returns_are_limited() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function many_returns()
+            local chunks = {}
+            for i = 1, 100 do
+                chunks[i] = string.rep("r", 1024)
+            end
+            return table.unpack(chunks)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "many_returns", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "returned values are too large");
+    assert!(api.get_receipt(tx.hash()).is_none());
+}
+
+#[test]
+fn contract_out_of_memory() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function hoard()
+            local chunks = {}
+            for i = 1, 64 do
+                chunks[i] = string.rep("x", 1024 * 1024)
+            end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // Copying memory costs gas, so it takes a larger gas limit to exhaust the memory.
+    let tx = api.call_contract_with_gas(&contract_pub, "hoard", vec![], DEFAULT_MAX_GAS);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 4, "Out of memory");
+}
+
+#[test]
+fn contract_transfer_errors() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, _) = api.create_wallet(ALICE_NAME);
+
+    let code = r#"
+        function try_transfer(to, amount)
+            local ok, err = pcall(transfer, to, amount)
+            state["ok"] = ok
+            state["err"] = tostring(err)
+        end
+
+        function force_transfer(to, amount)
+            transfer(to, amount)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_alice.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let alice = tx_alice.author().to_hex();
+    let tx = api.call_contract(&contract_pub, "try_transfer", vec![&alice, "1000"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    assert_eq!(api.get_state(&contract_pub, "ok"), Some(Value::Bool(false)));
+    assert_eq!(api.get_state(&contract_pub, "err"), Some(Value::from("insufficient funds")));
+
+    let tx_funds = api.call_contract(&contract_pub, "force_transfer", vec![&alice, "1000"]);
+    let tx_key = api.call_contract(&contract_pub, "force_transfer", vec!["zz", "1"]);
+    let tx_receiver = api.call_contract(&contract_pub, "force_transfer", vec![&"00".repeat(32), "1"]);
+    testkit.create_block();
+    api.assert_tx_error(tx_funds.hash(), 7, "Insufficient currency amount");
+    api.assert_tx_error(tx_key.hash(), 5, "Malformed public key");
+    api.assert_tx_error(tx_receiver.hash(), 6, "Receiver doesn't exist");
+
+    let wallet = api.get_wallet(contract_pub).unwrap();
+    assert_eq!(wallet.balance, 100);
+}
+
+#[test]
+fn contract_reads_msg() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function whoami()
+            state["caller"] = msg.caller
+            state["tx_hash"] = msg.tx_hash
+            state["contract"] = msg.contract
+        end
+
+        function impersonate()
+            msg.caller = msg.contract
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "whoami", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    assert_eq!(api.get_state(&contract_pub, "caller"), Some(Value::from(hex::encode(&tx.author()))));
+    assert_eq!(api.get_state(&contract_pub, "tx_hash"), Some(Value::from(hex::encode(&tx.hash()))));
+    assert_eq!(api.get_state(&contract_pub, "contract"), Some(Value::from(hex::encode(&contract_pub))));
+
+    let tx = api.call_contract(&contract_pub, "impersonate", vec![]);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(info["code"], 2);
+}
+
+#[test]
+fn contract_reads_chain() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function lock(blocks)
+            state["unlock_at"] = chain.height + blocks
+            state["created_after"] = chain.last_block_hash
+        end
+
+        function unlock()
+            if chain.height < state["unlock_at"] then
+                error("still locked")
+            end
+            state["unlocked"] = "yes"
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let last_block_hash = {
+        let snapshot = testkit.snapshot();
+        let schema = blockchain::Schema::new(&snapshot);
+        schema.block_hashes_by_height().last().unwrap()
+    };
+    let tx = api.call_contract(&contract_pub, "lock", vec!["2"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let unlock_at = testkit.height().0 as i64 + 2;
+    assert_eq!(api.get_state(&contract_pub, "unlock_at"), Some(Value::Integer(unlock_at)));
+    assert_eq!(
+        api.get_state(&contract_pub, "created_after"),
+        Some(Value::from(hex::encode(&last_block_hash)))
+    );
+
+    let tx = api.call_contract(&contract_pub, "unlock", vec![]);
+    testkit.create_block();
+    assert_eq!(api.tx_status(tx.hash())["type"], "error");
+
+    let tx = api.call_contract(&contract_pub, "unlock", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+}
+
+#[test]
+fn contract_records_returns() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function next_id()
+            local id = (state["last_id"] or 0) + 1
+            state["last_id"] = id
+            return id, "issued"
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx_first = api.call_contract(&contract_pub, "next_id", vec![]);
+    testkit.create_block();
+    let tx_second = api.call_contract(&contract_pub, "next_id", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx_first.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_second.hash(), &json!({ "type": "success" }));
+
+    let receipt = api.get_receipt(tx_first.hash()).unwrap();
+    assert_eq!(receipt.returns, vec![Value::Integer(1), Value::from("issued")]);
+    let receipt = api.get_receipt(tx_second.hash()).unwrap();
+    assert_eq!(receipt.returns, vec![Value::Integer(2), Value::from("issued")]);
+}
+
+#[test]
+fn contract_keeps_typed_state() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function deposit(user, amount)
+            local balances = state["balances"] or {}
+            balances[user] = (balances[user] or 0) + math.tointeger(amount)
+            state["balances"] = balances
+            state["active"] = true
+            state["rate"] = 0.5
+            state["history"] = { user, amount }
+        end
+
+        function check()
+            assert(math.type(state["balances"]["alice"]) == "integer")
+            assert(state["active"] == true)
+            assert(state["history"][1] == "alice")
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx_first = api.call_contract(&contract_pub, "deposit", vec!["alice", "10"]);
+    testkit.create_block();
+    let tx_second = api.call_contract(&contract_pub, "deposit", vec!["alice", "5"]);
+    testkit.create_block();
+    let tx_check = api.call_contract(&contract_pub, "check", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx_first.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_second.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_check.hash(), &json!({ "type": "success" }));
+
+    assert_eq!(
+        api.get_state(&contract_pub, "balances"),
+        Some(Value::Table(vec![(Value::from("alice"), Value::Integer(15))]))
+    );
+    assert_eq!(api.get_state(&contract_pub, "active"), Some(Value::Bool(true)));
+    assert_eq!(api.get_state(&contract_pub, "rate"), Some(Value::Number(0.5)));
+    assert_eq!(
+        api.get_state(&contract_pub, "history"),
+        Some(Value::Table(vec![
+            (Value::Integer(1), Value::from("alice")),
+            (Value::Integer(2), Value::from("5")),
+        ]))
+    );
+}
+
+#[test]
+fn contract_writes_touched_state() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function setup()
+            state["balances"] = { alice = 1 }
+            state["owner"] = "alice"
+        end
+
+        function bump()
+            state["balances"]["alice"] = state["balances"]["alice"] + 1
+        end
+
+        function clear()
+            state["balances"] = nil
+            state["owner"] = nil
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let empty_state_hash = api.get_contract(contract_pub).unwrap().state_hash;
+
+    let tx = api.call_contract(&contract_pub, "setup", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let setup_state_hash = api.get_contract(contract_pub).unwrap().state_hash;
+    assert_ne!(setup_state_hash, empty_state_hash);
+
+    let tx = api.call_contract(&contract_pub, "bump", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_ne!(api.get_contract(contract_pub).unwrap().state_hash, setup_state_hash);
+    assert_eq!(
+        api.get_state(&contract_pub, "balances"),
+        Some(Value::Table(vec![(Value::from("alice"), Value::Integer(2))]))
+    );
+    assert_eq!(api.get_state(&contract_pub, "owner"), Some(Value::from("alice")));
+
+    let tx = api.call_contract(&contract_pub, "clear", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert!(api.get_state(&contract_pub, "balances").is_none());
+    assert!(api.get_state(&contract_pub, "owner").is_none());
+    assert_eq!(api.get_contract(contract_pub).unwrap().state_hash, empty_state_hash);
+}
+
+#[test]
+fn contract_emits_events() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function notify(who)
+            emit("greeted", { who = who })
+            emit("counted", 1)
+        end
+
+        function fail()
+            emit("lost", true)
+            error("rollback")
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert!(api.get_contract_events(&contract_pub).is_empty());
+    assert!(api.get_tx_events(tx.hash()).is_empty());
+
+    // Unknown contracts have no events either.
+    let (stranger, _) = crypto::gen_keypair();
+    assert!(api.get_contract_events(&stranger).is_empty());
+
+    let tx = api.call_contract(&contract_pub, "notify", vec!["alice"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let events = api.get_tx_events(tx.hash());
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].name, "greeted");
+    assert_eq!(
+        events[0].data,
+        Value::Table(vec![(Value::from("who"), Value::from("alice"))])
+    );
+    assert_eq!(events[1].name, "counted");
+    assert_eq!(events[1].data, Value::Integer(1));
+    assert!(events.iter().all(|e| e.contract == contract_pub && e.tx_hash == tx.hash()));
+
+    // Events of failed calls are discarded.
+    let tx = api.call_contract(&contract_pub, "fail", vec![]);
+    testkit.create_block();
+    assert_eq!(api.tx_status(tx.hash())["type"], "error");
+    assert!(api.get_tx_events(tx.hash()).is_empty());
+
+    let events = api.get_contract_events(&contract_pub);
+    let names: Vec<_> = events.iter().map(|e| e.name.as_str()).collect();
+    assert_eq!(names, vec!["greeted", "counted"]);
+}
+
+#[test]
+fn contract_events_are_limited() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function big_event()
+            emit("big", string.rep("e", 8 * 1024))
+        end
+
+        function many_events(n)
+            for i = 1, tonumber(n) do
+                emit("tick", i)
+            end
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "big_event", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "event is too large");
+
+    let tx = api.call_contract(&contract_pub, "many_events", vec!["256"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_tx_events(tx.hash()).len(), 256);
+
+    let tx = api.call_contract(&contract_pub, "many_events", vec!["257"]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "too many events");
+}
+
+#[test]
+fn contract_calls_contract() {
+    let (mut testkit, api) = create_testkit();
+
+    let counter_code = r#"
+        function add(n)
+            state["total"] = (state["total"] or 0) + n
+            emit("added", n)
+            return state["total"], msg.caller
+        end
+
+        function boom()
+            error("boom")
+        end
+    "#;
+    let router_code = r#"
+        function route(counter, n)
+            return call(counter, "add", tonumber(n))
+        end
+
+        function route_caught(counter)
+            state["reached"] = true
+            pcall(call, counter, "boom")
+        end
+
+        function route_self()
+            return call(msg.contract, "route_self")
+        end
+    "#;
+    let (tx_counter, counter_pub) = api.create_contract(counter_code);
+    let (tx_router, router_pub) = api.create_contract(router_code);
+    testkit.create_block();
+    api.assert_tx_status(tx_counter.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_router.hash(), &json!({ "type": "success" }));
+    let counter = counter_pub.to_hex();
+
+    let tx = api.call_contract(&router_pub, "route", vec![&counter, "5"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let receipt = api.get_receipt(tx.hash()).unwrap();
+    assert_eq!(
+        receipt.returns,
+        vec![Value::Integer(5), Value::from(router_pub.to_hex())]
+    );
+    assert_eq!(api.get_state(&counter_pub, "total"), Some(Value::Integer(5)));
+    let events = api.get_contract_events(&counter_pub);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].tx_hash, tx.hash());
+
+    // A failed nested call fails the transaction even when caught.
+    let tx = api.call_contract(&router_pub, "route_caught", vec![&counter]);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(info["code"], 2);
+    assert!(api.get_state(&router_pub, "reached").is_none());
+
+    let tx = api.call_contract(&router_pub, "route_self", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "reentrant calls are not allowed");
+
+    let tx = api.call_contract(&router_pub, "route", vec![&router_pub.to_hex(), "1"]);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(api.get_state(&counter_pub, "total"), Some(Value::Integer(5)));
+}
+
+#[test]
+fn contract_call_depth_is_limited() {
+    let (mut testkit, api) = create_testkit();
+
+    // Calls the first contract in the chain of keys, passing it the rest.
+    let code = r#"
+        function relay(chain)
+            if chain == "" then
+                return 1
+            end
+            return call(chain:sub(1, 64), "relay", chain:sub(65)) + 1
+        end
+    "#;
+    let contracts: Vec<_> = (0..9).map(|_| api.create_contract(code)).collect();
+    testkit.create_block();
+    for (tx, _) in &contracts {
+        api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    }
+    let keys: Vec<_> = contracts.iter().map(|(_, key)| key.to_hex()).collect();
+
+    let tx = api.call_contract(&contracts[0].1, "relay", vec![&keys[1..8].concat()]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let receipt = api.get_receipt(tx.hash()).unwrap();
+    assert_eq!(receipt.returns, vec![Value::Integer(8)]);
+
+    let tx = api.call_contract(&contracts[0].1, "relay", vec![&keys[1..].concat()]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 2, "call depth exceeded");
+}
+
+#[test]
+fn contract_calls_are_charged() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function noop()
+        end
+
+        function call_many(contract, n)
+            for i = 1, tonumber(n) do
+                call(contract, "noop")
+            end
+        end
+    "#;
+    let (tx_callee, callee_pub) = api.create_contract(code);
+    let (tx_caller, caller_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_callee.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_caller.hash(), &json!({ "type": "success" }));
+
+    let callee = callee_pub.to_hex();
+    let tx = api.call_contract(&caller_pub, "call_many", vec![&callee, "10"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // The callee runs a few instructions, but each call sets up a new VM.
+    let tx = api.call_contract(&caller_pub, "call_many", vec![&callee, "1000"]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 3, "Out of gas");
+}
+
+#[test]
+fn contract_calls_share_memory_limit() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function alloc()
+            return #string.rep("x", 7 * 1024 * 1024)
+        end
+
+        function hold_and_call(contract)
+            local held = string.rep("x", 5 * 1024 * 1024)
+            call(contract, "alloc")
+            return #held
+        end
+    "#;
+    let (tx_callee, callee_pub) = api.create_contract(code);
+    let (tx_caller, caller_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_callee.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx_caller.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&callee_pub, "alloc", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    // The memory held by the caller is not available to the called contract.
+    let tx = api.call_contract(&caller_pub, "hold_and_call", vec![&callee_pub.to_hex()]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 4, "Out of memory");
+}
+
+#[test]
+fn contract_upgrade() {
+    let (mut testkit, api) = create_testkit();
+    let (owner_pk, owner_sk) = crypto::gen_keypair();
+    let (other_pk, other_sk) = crypto::gen_keypair();
+
+    let code = r#"
+        function set()
+            state["version"] = 1
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract_by(code, vec![], &owner_pk, &owner_sk);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_contract(contract_pub).unwrap().owner, owner_pk);
+
+    let tx = api.call_contract(&contract_pub, "set", vec![]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let new_code = r#"
+        function migrate()
+            state["version"] = state["version"] + 1
+            state["migrated_by"] = msg.caller
+        end
+
+        function get()
+            return state["version"]
+        end
+    "#;
+    let tx = api.upgrade_contract(&contract_pub, new_code, &other_pk, &other_sk);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({ "type": "error", "code": 8, "description": "Not the contract owner" }),
+    );
+    assert_eq!(api.get_contract_code(contract_pub), Some(code.to_string()));
+
+    let tx = api.upgrade_contract(&contract_pub, new_code, &owner_pk, &owner_sk);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_contract_code(contract_pub), Some(new_code.to_string()));
+    assert_eq!(api.get_state(&contract_pub, "version"), Some(Value::Integer(2)));
+    assert_eq!(
+        api.get_state(&contract_pub, "migrated_by"),
+        Some(Value::from(owner_pk.to_hex()))
+    );
+
+    // The migration runs only with the upgrade.
+    let tx = api.call_contract(&contract_pub, "migrate", vec![]);
+    testkit.create_block();
+    api.assert_tx_error(tx.hash(), 10, "function `migrate` cannot be called directly");
+    assert_eq!(api.get_state(&contract_pub, "version"), Some(Value::Integer(2)));
+
+    let tx = api.transfer_ownership(&contract_pub, &other_pk, &owner_pk, &owner_sk);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_contract(contract_pub).unwrap().owner, other_pk);
+
+    // Code without `migrate` replaces the old one as is.
+    let tx = api.upgrade_contract(&contract_pub, code, &other_pk, &other_sk);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_contract_code(contract_pub), Some(code.to_string()));
+    assert_eq!(api.get_state(&contract_pub, "version"), Some(Value::Integer(2)));
+}
+
+#[test]
+fn contract_constructor() {
+    let (mut testkit, api) = create_testkit();
+    let (owner_pk, owner_sk) = crypto::gen_keypair();
+
+    let code = r#"
+        function init(name)
+            state["name"] = name
+            state["deployer"] = msg.caller
+        end
+    "#;
+    let (tx, token_pub) = api.create_contract_by(code, vec!["token"], &owner_pk, &owner_sk);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_state(&token_pub, "name"), Some(Value::from("token")));
+    assert_eq!(
+        api.get_state(&token_pub, "deployer"),
+        Some(Value::from(owner_pk.to_hex()))
+    );
+
+    let code = r#"
+        function init()
+            error("refused")
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract_with_args(code, vec![]);
+    testkit.create_block();
+    let info = api.tx_status(tx.hash());
+    assert_eq!(info["type"], "error");
+    assert_eq!(info["code"], 2);
+    assert!(api.get_contract(contract_pub).is_none());
+    api.assert_no_wallet(contract_pub);
+
+    // The constructor runs only once, neither transactions nor contracts can call it.
+    let code = r#"
+        function reinit(contract)
+            call(contract, "init", "other")
+        end
+    "#;
+    let (tx, caller_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx_direct = api.call_contract(&token_pub, "init", vec!["other"]);
+    let tx_nested = api.call_contract(&caller_pub, "reinit", vec![&token_pub.to_hex()]);
+    testkit.create_block();
+    api.assert_tx_error(tx_direct.hash(), 10, "function `init` cannot be called directly");
+    api.assert_tx_error(tx_nested.hash(), 10, "function `init` cannot be called directly");
+    assert_eq!(api.get_state(&token_pub, "name"), Some(Value::from("token")));
+
+    // Arguments cannot be passed to a contract without a constructor.
+    let code = r#"
+        function get()
+            return 1
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract_with_args(code, vec!["token"]);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({
+            "type": "error",
+            "code": 10,
+            "description": "arguments are given, but `init` is not defined"
+        }),
+    );
+    assert!(api.get_contract(contract_pub).is_none());
+}
+
+#[test]
+fn contract_receives_payment() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, key_alice) = api.create_wallet(ALICE_NAME);
+    let alice = tx_alice.author();
+
+    let code = r#"
+        function buy()
+            state["paid"] = msg.value
+        end
+
+        function refuse()
+            error("sold out")
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_alice.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract_with_value(&contract_pub, "buy", vec![], 30, &alice, &key_alice);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_state(&contract_pub, "paid"), Some(Value::Integer(30)));
+    assert_eq!(api.get_wallet(alice).unwrap().balance, 70);
+    assert_eq!(api.get_wallet(contract_pub).unwrap().balance, 130);
+
+    // Payment is refunded when the call fails.
+    let tx = api.call_contract_with_value(&contract_pub, "refuse", vec![], 30, &alice, &key_alice);
+    testkit.create_block();
+    assert_eq!(api.tx_status(tx.hash())["type"], "error");
+    assert_eq!(api.get_wallet(alice).unwrap().balance, 70);
+    assert_eq!(api.get_wallet(contract_pub).unwrap().balance, 130);
+
+    let tx = api.call_contract_with_value(&contract_pub, "buy", vec![], 1000, &alice, &key_alice);
+    testkit.create_block();
+    api.assert_tx_status(
+        tx.hash(),
+        &json!({ "type": "error", "code": 7, "description": "Insufficient currency amount" }),
+    );
+}
+
+#[test]
+fn contract_bigint_math() {
+    let (mut testkit, api) = create_testkit();
+
+    let code = r#"
+        function split(total, parts)
+            local share = bigint(total) // parts
+            state["share"] = share
+            state["rest"] = bigint(total) % parts
+            return tostring(share * parts), share < bigint(total)
+        end
+
+        function overflow()
+            local max = bigint("170141183460469231731687303715884105727")
+            return tostring(max + 1)
+        end
+
+        function divide_by_zero()
+            return tostring(bigint(1) / 0)
+        end
+
+        function compare()
+            return bigint(0) == 0, bigint(0):eq(0), bigint(2):eq("2"), bigint(2) == bigint(2)
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "split", vec!["100", "3"]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    assert_eq!(api.get_state(&contract_pub, "share"), Some(Value::Integer(33)));
+    assert_eq!(api.get_state(&contract_pub, "rest"), Some(Value::Integer(1)));
+    let receipt = api.get_receipt(tx.hash()).unwrap();
+    assert_eq!(receipt.returns, vec![Value::from("99"), Value::Bool(true)]);
+
+    // `==` does not convert numbers to bigints, `eq` does.
+    let result = api.call_view(&contract_pub, "compare", vec![]).unwrap();
+    assert_eq!(
+        result.returns,
+        vec![json!(false), json!(true), json!(true), json!(true)]
+    );
+
+    for fn_name in &["overflow", "divide_by_zero"] {
+        let tx = api.call_contract(&contract_pub, fn_name, vec![]);
+        testkit.create_block();
+        let info = api.tx_status(tx.hash());
+        assert_eq!(info["type"], "error");
+        assert_eq!(info["code"], 2);
+    }
+}
+
+#[test]
+fn contract_transfer_rejects_invalid_amounts() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, _) = api.create_wallet(ALICE_NAME);
+    let alice = tx_alice.author().to_hex();
+
+    let code = r#"
+        function pay(to, amount)
+            transfer(to, load("return " .. amount)())
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    for amount in &["2.5", "-1", "{}"] {
+        let tx = api.call_contract(&contract_pub, "pay", vec![&alice, amount]);
+        testkit.create_block();
+        api.assert_tx_error(tx.hash(), 12, "Invalid currency amount");
+    }
+
+    for amount in &["3.0", "bigint(2)"] {
+        let tx = api.call_contract(&contract_pub, "pay", vec![&alice, amount]);
+        testkit.create_block();
+        api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    }
+    assert_eq!(api.get_wallet(contract_pub).unwrap().balance, 95);
+}
+
+#[test]
+fn contract_reads_balances() {
+    let (mut testkit, api) = create_testkit();
+    let (tx_alice, _) = api.create_wallet(ALICE_NAME);
+    let alice = tx_alice.author().to_hex();
+
+    let code = r#"
+        function pay_out(to)
+            local before = self_balance()
+            transfer(to, before)
+            return before, self_balance(), balance(to)
+        end
+
+        function unknown(owner)
+            return balance(owner) == nil
+        end
+    "#;
+    let (tx, contract_pub) = api.create_contract(code);
+    testkit.create_block();
+    api.assert_tx_status(tx_alice.hash(), &json!({ "type": "success" }));
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+
+    let tx = api.call_contract(&contract_pub, "pay_out", vec![&alice]);
+    testkit.create_block();
+    api.assert_tx_status(tx.hash(), &json!({ "type": "success" }));
+    let receipt = api.get_receipt(tx.hash()).unwrap();
+    assert_eq!(
+        receipt.returns,
+        vec![Value::Integer(100), Value::Integer(0), Value::Integer(200)]
+    );
+
+    let (stranger, _) = crypto::gen_keypair();
+    let result = api
+        .call_view(&contract_pub, "unknown", vec![&stranger.to_hex()])
+        .unwrap();
+    assert_eq!(result.returns, vec![json!(true)]);
+    assert!(api.call_view(&contract_pub, "unknown", vec!["zz"]).is_err());
+}